@@ -0,0 +1,523 @@
+use crate::context::RequestContext;
+use crate::db::Database;
+use crate::metrics::Metrics;
+use chrono::{DateTime, SecondsFormat, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use utoipa::{IntoParams, ToSchema};
+
+/// Advisory lock key serializing appends so that each entry chains onto the
+/// latest one.
+const AUDIT_CHAIN_LOCK: i64 = 0x7477_6175_6469_7400;
+
+/// Entries read at a time when verifying the chain.
+const VERIFY_BATCH_SIZE: i64 = 1000;
+
+/// `prev_hash` of the very first entry.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A single mutating control-plane call.
+///
+/// Entries form a hash chain: `hash` covers the entry's content and the
+/// `prev_hash` of the entry before it, so editing or removing any row breaks
+/// verification of every row after it.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+#[schema(as = AuditEntry)]
+pub struct AuditEntry {
+    /// Unique entry identifier, increasing in append order
+    #[schema(example = 42)]
+    pub id: i64,
+    /// Caller identity
+    #[schema(example = "ops@example.com")]
+    pub actor: String,
+    /// Request ID of the call (`X-Request-Id`)
+    #[schema(example = "5f0c6f9e-2d7b-4c43-9a43-0f6c2b7c9f11")]
+    pub request_id: String,
+    /// Source IP of the caller
+    #[schema(example = "203.0.113.7")]
+    pub source_ip: Option<String>,
+    /// What was attempted
+    #[schema(example = "project.suspend")]
+    pub action: String,
+    /// Kind of resource acted on
+    #[schema(example = "platform_project")]
+    pub resource_type: String,
+    /// Identifier of the resource acted on, when known
+    #[schema(example = "1")]
+    pub resource_id: Option<String>,
+    /// Resource state before the call
+    #[schema(value_type = Option<Object>)]
+    pub before: Option<Value>,
+    /// Resource state after the call
+    #[schema(value_type = Option<Object>)]
+    pub after: Option<Value>,
+    /// Changed fields, as `{"field": {"before": ..., "after": ...}}`
+    #[schema(value_type = Object)]
+    pub diff: Value,
+    /// `success` or `failure`
+    #[schema(example = "success")]
+    pub outcome: String,
+    /// HTTP status returned to the caller
+    #[schema(example = 200)]
+    pub status_code: i32,
+    /// Error message for failed calls
+    pub error: Option<String>,
+    /// When the call was recorded
+    pub created_at: DateTime<Utc>,
+    /// Hash of the previous entry
+    pub prev_hash: String,
+    /// SHA-256 over this entry's content and `prev_hash`
+    pub hash: String,
+}
+
+/// An audit entry to append.
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    pub actor: String,
+    pub request_id: String,
+    pub source_ip: Option<String>,
+    pub action: String,
+    pub resource_type: String,
+    pub resource_id: Option<String>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub status_code: u16,
+    pub error: Option<String>,
+}
+
+impl NewAuditEntry {
+    /// Starts an entry for a call made in `ctx`, assuming success until told otherwise.
+    pub fn new(
+        ctx: &RequestContext,
+        action: &str,
+        resource_type: &str,
+        resource_id: Option<String>,
+    ) -> Self {
+        Self {
+            actor: ctx.actor.clone(),
+            request_id: ctx.request_id.clone(),
+            source_ip: ctx.source_ip.clone(),
+            action: action.to_string(),
+            resource_type: resource_type.to_string(),
+            resource_id,
+            before: None,
+            after: None,
+            status_code: 200,
+            error: None,
+        }
+    }
+
+    /// Starts an entry for a change made in the background on behalf of
+    /// `actor`, such as by a job; `request_id` names what made it, e.g. `job-42`.
+    pub fn background(
+        actor: &str,
+        request_id: String,
+        action: &str,
+        resource_type: &str,
+        resource_id: Option<String>,
+    ) -> Self {
+        Self {
+            actor: actor.to_string(),
+            request_id,
+            source_ip: None,
+            action: action.to_string(),
+            resource_type: resource_type.to_string(),
+            resource_id,
+            before: None,
+            after: None,
+            status_code: 200,
+            error: None,
+        }
+    }
+
+    pub fn before<T: Serialize>(mut self, value: Option<&T>) -> Self {
+        self.before = value.and_then(|v| serde_json::to_value(v).ok());
+        self
+    }
+
+    pub fn after<T: Serialize>(mut self, value: Option<&T>) -> Self {
+        self.after = value.and_then(|v| serde_json::to_value(v).ok());
+        self
+    }
+
+    pub fn status(mut self, status_code: u16) -> Self {
+        self.status_code = status_code;
+        self
+    }
+
+    pub fn error<E: std::fmt::Display>(mut self, error: Option<E>) -> Self {
+        self.error = error.map(|e| e.to_string());
+        self
+    }
+}
+
+/// Filters for listing audit entries. All filters are optional and combined with AND.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    /// Only entries by this actor
+    pub actor: Option<String>,
+    /// Only entries for this action, e.g. `project.suspend`
+    pub action: Option<String>,
+    /// Only entries for this resource type, e.g. `platform_project`
+    pub resource_type: Option<String>,
+    /// Only entries for this resource ID
+    pub resource_id: Option<String>,
+    /// Only entries with this outcome: `success` or `failure`
+    pub outcome: Option<String>,
+    /// Only entries for this request ID
+    pub request_id: Option<String>,
+    /// Only entries recorded at or after this time (RFC 3339)
+    pub from: Option<DateTime<Utc>>,
+    /// Only entries recorded before this time (RFC 3339)
+    pub to: Option<DateTime<Utc>>,
+    /// Only entries with an ID lower than this, for paging backwards
+    pub before_id: Option<i64>,
+    /// Maximum number of entries to return (default 100, max 1000)
+    pub limit: Option<i64>,
+}
+
+/// Result of re-computing the audit hash chain.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[schema(as = AuditVerification)]
+pub struct AuditVerification {
+    /// Whether every entry's hash matches its content and predecessor
+    pub valid: bool,
+    /// Number of entries checked
+    pub entries_checked: i64,
+    /// First entry whose hash does not verify, if any
+    pub first_invalid_id: Option<i64>,
+}
+
+const AUDIT_COLUMNS: &str = "id, actor, request_id, source_ip, action, resource_type, resource_id, \
+     before, after, diff, outcome, status_code, error, created_at, prev_hash, hash";
+
+/// Top-level fields whose values differ between `before` and `after`.
+pub fn json_diff(before: Option<&Value>, after: Option<&Value>) -> Value {
+    let empty = serde_json::Map::new();
+    let before = before.and_then(Value::as_object).unwrap_or(&empty);
+    let after = after.and_then(Value::as_object).unwrap_or(&empty);
+
+    let mut diff = serde_json::Map::new();
+    for key in before.keys().chain(after.keys()) {
+        let (old, new) = (before.get(key), after.get(key));
+        if old != new && !diff.contains_key(key) {
+            diff.insert(
+                key.clone(),
+                serde_json::json!({ "before": old, "after": new }),
+            );
+        }
+    }
+    Value::Object(diff)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn entry_hash(
+    prev_hash: &str,
+    actor: &str,
+    request_id: &str,
+    source_ip: Option<&str>,
+    action: &str,
+    resource_type: &str,
+    resource_id: Option<&str>,
+    before: Option<&Value>,
+    after: Option<&Value>,
+    outcome: &str,
+    status_code: i32,
+    error: Option<&str>,
+    created_at: &DateTime<Utc>,
+) -> String {
+    // serde_json objects keep their keys sorted, so this serialization is stable
+    // across a round trip through JSONB
+    let content = serde_json::json!([
+        actor,
+        request_id,
+        source_ip,
+        action,
+        resource_type,
+        resource_id,
+        before,
+        after,
+        outcome,
+        status_code,
+        error,
+        created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+    ]);
+
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(content.to_string().as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+impl AuditEntry {
+    fn computed_hash(&self) -> String {
+        entry_hash(
+            &self.prev_hash,
+            &self.actor,
+            &self.request_id,
+            self.source_ip.as_deref(),
+            &self.action,
+            &self.resource_type,
+            self.resource_id.as_deref(),
+            self.before.as_ref(),
+            self.after.as_ref(),
+            &self.outcome,
+            self.status_code,
+            self.error.as_deref(),
+            &self.created_at,
+        )
+    }
+}
+
+impl Database {
+    /// Appends an entry to the audit chain.
+    pub async fn record_audit_entry(&self, entry: NewAuditEntry) -> anyhow::Result<AuditEntry> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(AUDIT_CHAIN_LOCK)
+            .execute(&mut *tx)
+            .await?;
+        let prev_hash: String =
+            sqlx::query_scalar("SELECT hash FROM audit_log ORDER BY id DESC LIMIT 1")
+                .fetch_optional(&mut *tx)
+                .await?
+                .unwrap_or_else(|| GENESIS_HASH.to_string());
+
+        let outcome = if (200..400).contains(&entry.status_code) {
+            "success"
+        } else {
+            "failure"
+        };
+        let status_code = i32::from(entry.status_code);
+        let diff = json_diff(entry.before.as_ref(), entry.after.as_ref());
+        // Postgres keeps microseconds; truncate so the hash verifies after a round trip
+        let created_at = Utc::now().trunc_subsecs(6);
+        let hash = entry_hash(
+            &prev_hash,
+            &entry.actor,
+            &entry.request_id,
+            entry.source_ip.as_deref(),
+            &entry.action,
+            &entry.resource_type,
+            entry.resource_id.as_deref(),
+            entry.before.as_ref(),
+            entry.after.as_ref(),
+            outcome,
+            status_code,
+            entry.error.as_deref(),
+            &created_at,
+        );
+
+        let recorded = sqlx::query_as::<_, AuditEntry>(&format!(
+            r#"
+            INSERT INTO audit_log (actor, request_id, source_ip, action, resource_type, resource_id,
+                                   before, after, diff, outcome, status_code, error, created_at,
+                                   prev_hash, hash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+            RETURNING {AUDIT_COLUMNS}
+            "#,
+        ))
+        .bind(&entry.actor)
+        .bind(&entry.request_id)
+        .bind(&entry.source_ip)
+        .bind(&entry.action)
+        .bind(&entry.resource_type)
+        .bind(&entry.resource_id)
+        .bind(&entry.before)
+        .bind(&entry.after)
+        .bind(&diff)
+        .bind(outcome)
+        .bind(status_code)
+        .bind(&entry.error)
+        .bind(created_at)
+        .bind(&prev_hash)
+        .bind(&hash)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(recorded)
+    }
+
+    /// Appends an entry for a change that has already been made, where a
+    /// failure to audit is logged and counted rather than failing the change.
+    pub async fn record_audit(&self, entry: NewAuditEntry, metrics: &Metrics) {
+        let action = entry.action.clone();
+        if let Err(e) = self.record_audit_entry(entry).await {
+            tracing::error!("Failed to record audit entry for {}: {}", action, e);
+            metrics.audit_write_failures_total.inc();
+        }
+    }
+
+    /// Lists audit entries matching `query`, newest first.
+    pub async fn list_audit_entries(&self, query: &AuditQuery) -> anyhow::Result<Vec<AuditEntry>> {
+        let mut builder: QueryBuilder<Postgres> =
+            QueryBuilder::new(format!("SELECT {AUDIT_COLUMNS} FROM audit_log WHERE TRUE"));
+
+        if let Some(actor) = &query.actor {
+            builder.push(" AND actor = ").push_bind(actor);
+        }
+        if let Some(action) = &query.action {
+            builder.push(" AND action = ").push_bind(action);
+        }
+        if let Some(resource_type) = &query.resource_type {
+            builder.push(" AND resource_type = ").push_bind(resource_type);
+        }
+        if let Some(resource_id) = &query.resource_id {
+            builder.push(" AND resource_id = ").push_bind(resource_id);
+        }
+        if let Some(outcome) = &query.outcome {
+            builder.push(" AND outcome = ").push_bind(outcome);
+        }
+        if let Some(request_id) = &query.request_id {
+            builder.push(" AND request_id = ").push_bind(request_id);
+        }
+        if let Some(from) = query.from {
+            builder.push(" AND created_at >= ").push_bind(from);
+        }
+        if let Some(to) = query.to {
+            builder.push(" AND created_at < ").push_bind(to);
+        }
+        if let Some(before_id) = query.before_id {
+            builder.push(" AND id < ").push_bind(before_id);
+        }
+        builder
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(query.limit.unwrap_or(100).clamp(1, 1000));
+
+        let entries = builder
+            .build_query_as::<AuditEntry>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(entries)
+    }
+
+    /// Re-computes every hash in the chain, oldest first, reading it in batches
+    /// of [`VERIFY_BATCH_SIZE`] entries.
+    pub async fn verify_audit_chain(&self) -> anyhow::Result<AuditVerification> {
+        let mut expected_prev = GENESIS_HASH.to_string();
+        let mut checked = 0;
+        let mut after_id = 0;
+        loop {
+            let entries = sqlx::query_as::<_, AuditEntry>(&format!(
+                "SELECT {AUDIT_COLUMNS} FROM audit_log WHERE id > $1 ORDER BY id LIMIT $2"
+            ))
+            .bind(after_id)
+            .bind(VERIFY_BATCH_SIZE)
+            .fetch_all(&self.pool)
+            .await?;
+
+            for entry in &entries {
+                checked += 1;
+                if entry.prev_hash != expected_prev || entry.computed_hash() != entry.hash {
+                    return Ok(AuditVerification {
+                        valid: false,
+                        entries_checked: checked,
+                        first_invalid_id: Some(entry.id),
+                    });
+                }
+                expected_prev = entry.hash.clone();
+            }
+            match entries.last() {
+                Some(last) if entries.len() as i64 == VERIFY_BATCH_SIZE => after_id = last.id,
+                _ => break,
+            }
+        }
+
+        Ok(AuditVerification {
+            valid: true,
+            entries_checked: checked,
+            first_invalid_id: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diffs_changed_top_level_fields() {
+        let before = json!({"name": "Acme", "plan": "free", "labels": {"team": "a"}, "gone": 1});
+        let after = json!({"name": "Acme", "plan": "pro", "labels": {"team": "b"}, "new": true});
+
+        assert_eq!(
+            json_diff(Some(&before), Some(&after)),
+            json!({
+                "plan": {"before": "free", "after": "pro"},
+                "labels": {"before": {"team": "a"}, "after": {"team": "b"}},
+                "gone": {"before": 1, "after": null},
+                "new": {"before": null, "after": true},
+            })
+        );
+    }
+
+    #[test]
+    fn diffs_creations_and_deletions() {
+        let project = json!({"name": "Acme", "plan": "free"});
+
+        assert_eq!(
+            json_diff(None, Some(&project)),
+            json!({
+                "name": {"before": null, "after": "Acme"},
+                "plan": {"before": null, "after": "free"},
+            })
+        );
+        assert_eq!(
+            json_diff(Some(&project), None),
+            json!({
+                "name": {"before": "Acme", "after": null},
+                "plan": {"before": "free", "after": null},
+            })
+        );
+        assert_eq!(json_diff(Some(&project), Some(&project)), json!({}));
+        assert_eq!(json_diff(None, None), json!({}));
+    }
+
+    fn hash(prev_hash: &str, after: &Value, status_code: i32) -> String {
+        let created_at = "2024-05-01T12:30:45.123456Z".parse().unwrap();
+        entry_hash(
+            prev_hash,
+            "key:ci",
+            "req-1",
+            Some("203.0.113.7"),
+            "project.update",
+            "platform_project",
+            Some("1"),
+            None,
+            Some(after),
+            "success",
+            status_code,
+            None,
+            &created_at,
+        )
+    }
+
+    #[test]
+    fn hashes_entries_deterministically() {
+        let after = json!({"plan": "pro", "name": "Acme"});
+        let original = hash(GENESIS_HASH, &after, 200);
+
+        assert_eq!(original.len(), 64);
+        assert_eq!(original, hash(GENESIS_HASH, &after, 200));
+        // Key order does not matter, as JSONB does not keep it
+        let reordered: Value = serde_json::from_str(r#"{"name": "Acme", "plan": "pro"}"#).unwrap();
+        assert_eq!(original, hash(GENESIS_HASH, &reordered, 200));
+    }
+
+    #[test]
+    fn chains_hashes_over_content() {
+        let after = json!({"plan": "pro"});
+        let original = hash(GENESIS_HASH, &after, 200);
+
+        assert_ne!(original, hash(&original, &after, 200));
+        assert_ne!(original, hash(GENESIS_HASH, &json!({"plan": "team"}), 200));
+        assert_ne!(original, hash(GENESIS_HASH, &after, 500));
+    }
+}
@@ -0,0 +1,538 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use axum::http::Method;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::config::AuthConfig;
+use crate::db::Database;
+use crate::platform::string_enum;
+use crate::validation::ValidationErrors;
+
+/// Prefix of every API key, so leaked keys are easy to spot in logs and scanners.
+const KEY_PREFIX: &str = "tw_";
+
+/// Characters of the key shown in listings to tell keys apart.
+const DISPLAY_PREFIX_LEN: usize = KEY_PREFIX.len() + 8;
+
+const API_KEY_COLUMNS: &str =
+    "id, name, prefix, scopes, created_by, created_at, expires_at, last_used_at, revoked_at";
+
+string_enum! {
+    /// Permission granted to an API key. `admin` implies every other scope and
+    /// also covers key management, revealing project credentials and exporting
+    /// and importing projects.
+    pub enum Scope {
+        ProjectsRead => "projects:read",
+        ProjectsWrite => "projects:write",
+        ProjectsLifecycle => "projects:lifecycle",
+        MetricsRead => "metrics:read",
+        AuditRead => "audit:read",
+        Admin => "admin",
+    }
+}
+
+/// The caller behind an authenticated request, stored in the request extensions.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// Actor name recorded in the audit log and lifecycle history
+    pub name: String,
+    /// Principal the key was minted for (its name); organization memberships
+    /// refer to principals, so they survive key rotation
+    pub principal: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl Identity {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+}
+
+/// An API key as stored in Postgres; the key itself is only kept as a hash.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ApiKey {
+    #[schema(example = 1)]
+    pub id: i64,
+    /// Principal the key belongs to; used as the actor in the audit log and to
+    /// match organization memberships
+    #[schema(example = "ci-deployer")]
+    pub name: String,
+    /// First characters of the key, to recognise it
+    #[schema(example = "tw_3f9a1c2e")]
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    /// Actor that minted the key
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    /// The key stops working after this time
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Request body for minting an API key
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CreateApiKey {
+    #[schema(example = "ci-deployer")]
+    pub name: String,
+    #[schema(example = json!(["projects:read", "projects:lifecycle"]))]
+    pub scopes: Vec<String>,
+    /// Optional expiry time
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A validated [`CreateApiKey`].
+#[derive(Debug, Clone)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl CreateApiKey {
+    pub fn validate(self) -> Result<NewApiKey, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        let name = self.name.trim().to_string();
+        if name.is_empty() || name.len() > 100 {
+            errors.add("name", "must be between 1 and 100 characters");
+        }
+
+        let mut scopes = Vec::new();
+        for scope in &self.scopes {
+            match scope.parse::<Scope>() {
+                Ok(scope) if !scopes.contains(&scope) => scopes.push(scope),
+                Ok(_) => {}
+                Err(e) => errors.add("scopes", format!("'{}' {}", scope, e)),
+            }
+        }
+        if self.scopes.is_empty() {
+            errors.add("scopes", "must contain at least one scope");
+        }
+
+        if self.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            errors.add("expires_at", "must be in the future");
+        }
+
+        errors.into_result(NewApiKey {
+            name,
+            scopes,
+            expires_at: self.expires_at,
+        })
+    }
+}
+
+/// A freshly minted key. `key` is returned once and never stored.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+    /// The secret to send as `Authorization: Bearer <key>`
+    #[schema(example = "tw_3f9a1c2e5b7d...")]
+    pub key: String,
+}
+
+/// Why a request was not let through.
+#[derive(Debug)]
+pub enum AuthError {
+    /// No key, or an unknown, revoked or expired one
+    Unauthenticated,
+    /// A valid key without the scope the route needs
+    Forbidden(Scope),
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Unauthenticated => write!(f, "Missing or invalid API key"),
+            AuthError::Forbidden(scope) => write!(f, "API key lacks the '{}' scope", scope),
+            AuthError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+/// Resolves API keys to identities and decides which scope each route needs.
+pub struct Authenticator {
+    db: Arc<Database>,
+    enabled: bool,
+    public_metrics: bool,
+    bootstrap_key_hash: Option<String>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig, db: Arc<Database>) -> Self {
+        Self {
+            db,
+            enabled: config.enabled,
+            public_metrics: config.public_metrics,
+            bootstrap_key_hash: config.bootstrap_key.as_deref().map(hash_key),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Scope needed to call `method path`, or `None` for public routes.
+    /// Unknown API routes require `admin` so new endpoints fail closed.
+    pub fn required_scope(&self, method: &Method, path: &str) -> Option<Scope> {
+        let path = path.trim_end_matches('/');
+        if matches!(path, "" | "/health" | "/ready" | "/api/openapi.json")
+            || path.starts_with("/static/")
+            || path.starts_with("/api/docs")
+        {
+            return None;
+        }
+
+        if path == "/metrics" || path == "/api/v1/status" {
+            return (!self.public_metrics).then_some(Scope::MetricsRead);
+        }
+        if path.starts_with("/api/v1/audit") {
+            return Some(Scope::AuditRead);
+        }
+        if let Some(rest) = path.strip_prefix("/api/v1/organizations") {
+            // Creating organizations is for admins; member changes are further
+            // restricted to organization owners by the handlers
+            return Some(match method {
+                &Method::GET | &Method::HEAD => Scope::ProjectsRead,
+                &Method::POST if rest.is_empty() => Scope::Admin,
+                _ => Scope::ProjectsWrite,
+            });
+        }
+        if path.starts_with("/api/v1/plans") {
+            // Anyone who can see projects can see the plan catalog; changing it is for admins
+            return Some(match method {
+                &Method::GET | &Method::HEAD => Scope::ProjectsRead,
+                _ => Scope::Admin,
+            });
+        }
+        if path == "/api/v1/platform/usage" {
+            return Some(Scope::ProjectsRead);
+        }
+        if path.starts_with("/api/v1/jobs") {
+            // Retrying a job repeats the lifecycle operation it carries out
+            return Some(match method {
+                &Method::GET | &Method::HEAD => Scope::ProjectsRead,
+                _ => Scope::ProjectsLifecycle,
+            });
+        }
+        if path == "/api/v1/platform/reconcile/plan" {
+            return Some(Scope::ProjectsRead);
+        }
+        if path.starts_with("/api/v1/platform/reconcile") {
+            // Applying creates, changes and deletes projects across organizations
+            return Some(Scope::Admin);
+        }
+        if path.starts_with("/api/v1/webhooks") {
+            // Subscriptions receive every project's events and hold signing secrets
+            return Some(Scope::Admin);
+        }
+        if let Some(rest) = path.strip_prefix("/api/v1/platform/projects") {
+            // Exports carry every project's connection string; imports bypass the provisioner
+            if rest.ends_with("/credentials") || rest == "/export" || rest == "/import" {
+                return Some(Scope::Admin);
+            }
+            // Also covers `/projects:bulk-suspend` and `/projects:bulk-resume`, with the
+            // colon percent-encoded or not
+            let lifecycle = rest.ends_with("suspend") || rest.ends_with("resume");
+            if (method == Method::POST && lifecycle) || method == Method::DELETE {
+                return Some(Scope::ProjectsLifecycle);
+            }
+            if method == Method::GET || method == Method::HEAD {
+                return Some(Scope::ProjectsRead);
+            }
+            // Schedules suspend and resume projects later, so managing them takes the same scope
+            if rest.contains("/schedules") {
+                return Some(Scope::ProjectsLifecycle);
+            }
+            return Some(Scope::ProjectsWrite);
+        }
+
+        Some(Scope::Admin)
+    }
+
+    /// Resolves the bearer token of a request to an identity.
+    pub async fn authenticate(&self, token: Option<&str>) -> Result<Identity, AuthError> {
+        let token = token.ok_or(AuthError::Unauthenticated)?;
+        let hash = hash_key(token);
+
+        if self.bootstrap_key_hash.as_deref() == Some(hash.as_str()) {
+            return Ok(Identity {
+                name: "bootstrap".to_string(),
+                principal: "bootstrap".to_string(),
+                scopes: vec![Scope::Admin],
+            });
+        }
+
+        let key = self
+            .db
+            .touch_api_key(&hash)
+            .await
+            .map_err(AuthError::Database)?
+            .ok_or(AuthError::Unauthenticated)?;
+
+        Ok(Identity {
+            name: format!("key:{}", key.name),
+            principal: key.name,
+            scopes: key.scopes,
+        })
+    }
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+fn generate_key() -> String {
+    let mut bytes = [0u8; 24];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", KEY_PREFIX, hex::encode(bytes))
+}
+
+impl Database {
+    /// Mints a new API key. The returned key is not stored and cannot be shown again.
+    pub async fn create_api_key(
+        &self,
+        input: NewApiKey,
+        created_by: &str,
+    ) -> anyhow::Result<CreatedApiKey> {
+        let key = generate_key();
+
+        let api_key = sqlx::query_as::<_, ApiKey>(&format!(
+            r#"
+            INSERT INTO api_keys (name, prefix, key_hash, scopes, created_by, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING {API_KEY_COLUMNS}
+            "#,
+        ))
+        .bind(&input.name)
+        .bind(&key[..DISPLAY_PREFIX_LEN])
+        .bind(hash_key(&key))
+        .bind(&input.scopes)
+        .bind(created_by)
+        .bind(input.expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CreatedApiKey { api_key, key })
+    }
+
+    /// Lists all API keys, including revoked ones, newest first.
+    pub async fn list_api_keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+        let keys = sqlx::query_as::<_, ApiKey>(&format!(
+            "SELECT {API_KEY_COLUMNS} FROM api_keys ORDER BY id DESC"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(keys)
+    }
+
+    /// Revokes a key. Returns `None` if it does not exist; revoking twice keeps
+    /// the original revocation time.
+    pub async fn revoke_api_key(&self, id: i64) -> anyhow::Result<Option<ApiKey>> {
+        let key = sqlx::query_as::<_, ApiKey>(&format!(
+            r#"
+            UPDATE api_keys SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1
+            RETURNING {API_KEY_COLUMNS}
+            "#,
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(key)
+    }
+
+    /// Looks up a usable key by hash and records that it was used.
+    async fn touch_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, sqlx::Error> {
+        sqlx::query_as::<_, ApiKey>(&format!(
+            r#"
+            UPDATE api_keys SET last_used_at = NOW()
+            WHERE key_hash = $1
+              AND revoked_at IS NULL
+              AND (expires_at IS NULL OR expires_at > NOW())
+            RETURNING {API_KEY_COLUMNS}
+            "#,
+        ))
+        .bind(key_hash)
+        .fetch_optional(&self.pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authenticator(public_metrics: bool) -> Authenticator {
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/telemetrywatch").unwrap();
+        let config = AuthConfig {
+            enabled: true,
+            bootstrap_key: None,
+            public_metrics,
+        };
+        Authenticator::new(
+            &config,
+            Arc::new(Database {
+                pool,
+                max_connections: 1,
+                keyring: None,
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn leaves_public_routes_open() {
+        let auth = authenticator(false);
+
+        for path in [
+            "/",
+            "/health",
+            "/ready/",
+            "/api/openapi.json",
+            "/api/docs/index.html",
+        ] {
+            assert_eq!(auth.required_scope(&Method::GET, path), None, "{}", path);
+        }
+        assert_eq!(
+            auth.required_scope(&Method::GET, "/metrics"),
+            Some(Scope::MetricsRead)
+        );
+        assert_eq!(
+            authenticator(true).required_scope(&Method::GET, "/metrics"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn scopes_project_routes() {
+        let auth = authenticator(false);
+        let cases = [
+            (
+                Method::GET,
+                "/api/v1/platform/projects",
+                Scope::ProjectsRead,
+            ),
+            (
+                Method::POST,
+                "/api/v1/platform/projects",
+                Scope::ProjectsWrite,
+            ),
+            (
+                Method::PATCH,
+                "/api/v1/platform/projects/1",
+                Scope::ProjectsWrite,
+            ),
+            (
+                Method::DELETE,
+                "/api/v1/platform/projects/1",
+                Scope::ProjectsLifecycle,
+            ),
+            (
+                Method::POST,
+                "/api/v1/platform/projects/1/suspend",
+                Scope::ProjectsLifecycle,
+            ),
+            (
+                Method::POST,
+                "/api/v1/platform/projects:bulk-resume",
+                Scope::ProjectsLifecycle,
+            ),
+            (
+                Method::POST,
+                "/api/v1/platform/projects%3Abulk-suspend",
+                Scope::ProjectsLifecycle,
+            ),
+            (
+                Method::POST,
+                "/api/v1/platform/projects/1/schedules",
+                Scope::ProjectsLifecycle,
+            ),
+            (
+                Method::GET,
+                "/api/v1/platform/projects/1/credentials",
+                Scope::Admin,
+            ),
+            (
+                Method::GET,
+                "/api/v1/platform/projects/export",
+                Scope::Admin,
+            ),
+            (
+                Method::POST,
+                "/api/v1/platform/projects/import",
+                Scope::Admin,
+            ),
+            (
+                Method::POST,
+                "/api/v1/platform/reconcile/plan",
+                Scope::ProjectsRead,
+            ),
+            (
+                Method::POST,
+                "/api/v1/platform/reconcile/apply",
+                Scope::Admin,
+            ),
+        ];
+        for (method, path, scope) in cases {
+            assert_eq!(
+                auth.required_scope(&method, path),
+                Some(scope),
+                "{} {}",
+                method,
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn scopes_other_routes() {
+        let auth = authenticator(false);
+        let cases = [
+            (Method::GET, "/api/v1/audit/verify", Scope::AuditRead),
+            (Method::GET, "/api/v1/organizations/1", Scope::ProjectsRead),
+            (Method::POST, "/api/v1/organizations", Scope::Admin),
+            (
+                Method::PUT,
+                "/api/v1/organizations/1/members/key:ci",
+                Scope::ProjectsWrite,
+            ),
+            (Method::GET, "/api/v1/plans", Scope::ProjectsRead),
+            (Method::PUT, "/api/v1/plans/pro", Scope::Admin),
+            (Method::GET, "/api/v1/jobs/1", Scope::ProjectsRead),
+            (
+                Method::POST,
+                "/api/v1/jobs/1/retry",
+                Scope::ProjectsLifecycle,
+            ),
+            (Method::GET, "/api/v1/webhooks", Scope::Admin),
+            (Method::POST, "/api/v1/auth/keys", Scope::Admin),
+        ];
+        for (method, path, scope) in cases {
+            assert_eq!(
+                auth.required_scope(&method, path),
+                Some(scope),
+                "{} {}",
+                method,
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_closed_on_unknown_api_routes() {
+        let auth = authenticator(false);
+
+        assert_eq!(
+            auth.required_scope(&Method::GET, "/api/v1/something-new"),
+            Some(Scope::Admin)
+        );
+    }
+}
@@ -7,12 +7,28 @@ pub struct Config {
     pub server: ServerConfig,
     pub database: DatabaseConfig,
     pub metrics: MetricsConfig,
+    pub encryption: EncryptionConfig,
+    pub auth: AuthConfig,
+    pub probe: ProbeConfig,
+    pub tenant_stats: TenantStatsConfig,
+    pub quota: QuotaConfig,
+    pub metering: MeteringConfig,
+    pub idempotency: IdempotencyConfig,
+    pub webhooks: WebhookConfig,
+    pub schedules: ScheduleConfig,
+    pub idle: IdleConfig,
+    pub provisioner: ProvisionerConfig,
+    pub enforcement: EnforcementConfig,
+    pub jobs: JobConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Addresses or CIDR networks of the reverse proxies in front of the app,
+    /// whose `X-Forwarded-For` hops are believed
+    pub trusted_proxies: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +40,193 @@ pub struct DatabaseConfig {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetricsConfig {
     pub enabled: bool,
+    /// Project label keys added as `label_<key>` labels to the `platform_projects` gauge
+    pub promoted_project_labels: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EncryptionConfig {
+    /// Master keys as (key ID, base64-encoded 32-byte key), from
+    /// `ENCRYPTION_KEYS=id1:base64,id2:base64`
+    pub keys: Vec<(String, String)>,
+    /// Key used for new encryptions; defaults to the first configured key
+    pub active_key_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeConfig {
+    /// Run the background health prober
+    pub enabled: bool,
+    /// Seconds between probe rounds
+    pub interval_secs: u64,
+    /// Timeout of each database and HTTP check, in seconds
+    pub timeout_secs: u64,
+    /// Projects probed at the same time
+    pub concurrency: usize,
+    /// Days of probe history kept in the database
+    pub retention_days: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TenantStatsConfig {
+    /// Collect statistics from project databases
+    pub enabled: bool,
+    /// Seconds between collection rounds
+    pub interval_secs: u64,
+    /// Timeout of each project's collection, in seconds
+    pub timeout_secs: u64,
+    /// Projects collected at the same time
+    pub concurrency: usize,
+    /// Connections kept open to each project database
+    pub pool_size: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaConfig {
+    /// Evaluate projects' usage against their plan's limits
+    pub enabled: bool,
+    /// Seconds between evaluations
+    pub interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeteringConfig {
+    /// Sample projects' usage into `usage_records`
+    pub enabled: bool,
+    /// Seconds between samples
+    pub interval_secs: u64,
+    /// Days daily usage records are kept (hourly ones follow the plan's retention)
+    pub daily_retention_days: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyConfig {
+    /// Seconds the response to a request with an `Idempotency-Key` is kept for replay
+    pub ttl_secs: u64,
+    /// Seconds without a sign of life after which a request's claim on its key
+    /// lapses and a retry may take it over
+    pub in_flight_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Deliver queued webhook events
+    pub enabled: bool,
+    /// Seconds between checks for due deliveries
+    pub poll_interval_secs: u64,
+    /// Timeout of each delivery attempt, in seconds
+    pub timeout_secs: u64,
+    /// Attempts before a delivery is marked failed
+    pub max_attempts: i32,
+    /// Seconds before the first retry; each further retry waits twice as long
+    pub retry_base_secs: u64,
+    /// Days finished deliveries are kept in the delivery log
+    pub retention_days: i64,
+    /// Addresses or CIDR networks that deliveries may reach although they are
+    /// not public, e.g. receivers on a private network
+    pub allowed_networks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    /// Run due project schedules
+    pub enabled: bool,
+    /// Seconds between checks for due schedules
+    pub poll_interval_secs: u64,
+    /// Seconds a run may be overdue, e.g. after downtime, before it is skipped as missed
+    pub misfire_grace_secs: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdleConfig {
+    /// Suspend projects idle for longer than their plan's `idle_suspend_after_hours`
+    pub enabled: bool,
+    /// Seconds between checks for idle projects
+    pub interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobConfig {
+    /// Jobs run at once by this instance
+    pub workers: usize,
+    /// Seconds an idle worker waits before checking for due jobs again
+    pub poll_interval_secs: u64,
+    /// Attempts before a job is given up on as dead
+    pub max_attempts: i32,
+    /// Seconds before the first retry; each further retry waits twice as long
+    pub retry_base_secs: u64,
+    /// Seconds a running job is leased to its worker before another may take it over
+    pub lease_secs: u64,
+    /// Days succeeded and failed jobs are kept
+    pub retention_days: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnforcementConfig {
+    /// Block access to a suspended project's database through its stored `db_url`
+    pub enabled: bool,
+    /// Roles whose LOGIN is revoked while their project is suspended
+    pub revoke_login_roles: Vec<String>,
+    /// Database connected to for enforcement, as the project database itself may refuse connections
+    pub maintenance_database: String,
+    /// Timeout of connecting and of each enforcement step, in seconds
+    pub timeout_secs: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProvisionerConfig {
+    /// Backend carrying out lifecycle transitions: `noop`, `postgres` or `http`
+    pub backend: String,
+    /// Admin connection string of the server the `postgres` backend creates databases on
+    pub postgres_url: Option<String>,
+    /// Management endpoint the `http` backend POSTs lifecycle actions to
+    pub http_url: Option<String>,
+    /// Bearer token sent to the management endpoint
+    pub http_token: Option<String>,
+    /// Timeout of each provisioner call, in seconds
+    pub timeout_secs: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Require API keys on the control plane (disable only for local development)
+    pub enabled: bool,
+    /// Admin key accepted without a database row, for minting the first keys
+    pub bootstrap_key: Option<String>,
+    /// Serve `/metrics` and `/api/v1/status` without a key, for scrapers
+    pub public_metrics: bool,
+}
+
+impl std::fmt::Debug for AuthConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthConfig")
+            .field("enabled", &self.enabled)
+            .field("bootstrap_key", &self.bootstrap_key.as_ref().map(|_| "<set>"))
+            .field("public_metrics", &self.public_metrics)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for ProvisionerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProvisionerConfig")
+            .field("backend", &self.backend)
+            .field("postgres_url", &self.postgres_url.as_ref().map(|_| "<set>"))
+            .field("http_url", &self.http_url)
+            .field("http_token", &self.http_token.as_ref().map(|_| "<set>"))
+            .field("timeout_secs", &self.timeout_secs)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let key_ids: Vec<&str> = self.keys.iter().map(|(id, _)| id.as_str()).collect();
+        f.debug_struct("EncryptionConfig")
+            .field("key_ids", &key_ids)
+            .field("active_key_id", &self.active_key_id)
+            .finish()
+    }
 }
 
 impl Default for Config {
@@ -35,6 +238,12 @@ impl Default for Config {
                     .ok()
                     .and_then(|p| p.parse().ok())
                     .unwrap_or(8080),
+                trusted_proxies: env::var("TRUSTED_PROXIES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|proxy| proxy.trim().to_string())
+                    .filter(|proxy| !proxy.is_empty())
+                    .collect(),
             },
             database: DatabaseConfig {
                 url: {
@@ -120,6 +329,265 @@ impl Default for Config {
                     .ok()
                     .and_then(|e| e.parse().ok())
                     .unwrap_or(true),
+                promoted_project_labels: env::var("METRICS_PROJECT_LABELS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|key| key.trim().to_string())
+                    .filter(|key| !key.is_empty())
+                    .collect(),
+            },
+            encryption: EncryptionConfig {
+                keys: env::var("ENCRYPTION_KEYS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter_map(|entry| entry.trim().split_once(':'))
+                    .map(|(id, key)| (id.trim().to_string(), key.trim().to_string()))
+                    .collect(),
+                active_key_id: env::var("ENCRYPTION_ACTIVE_KEY_ID")
+                    .ok()
+                    .filter(|id| !id.is_empty()),
+            },
+            auth: AuthConfig {
+                enabled: env::var("AUTH_ENABLED")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(true),
+                bootstrap_key: env::var("BOOTSTRAP_API_KEY")
+                    .ok()
+                    .map(|k| k.trim().to_string())
+                    .filter(|k| !k.is_empty()),
+                public_metrics: env::var("METRICS_PUBLIC")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(false),
+            },
+            probe: ProbeConfig {
+                enabled: env::var("PROBE_ENABLED")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(true),
+                interval_secs: env::var("PROBE_INTERVAL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(30),
+                timeout_secs: env::var("PROBE_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(5),
+                concurrency: env::var("PROBE_CONCURRENCY")
+                    .ok()
+                    .and_then(|c| c.parse().ok())
+                    .unwrap_or(8),
+                retention_days: env::var("PROBE_RETENTION_DAYS")
+                    .ok()
+                    .and_then(|d| d.parse().ok())
+                    .unwrap_or(7),
+            },
+            tenant_stats: TenantStatsConfig {
+                enabled: env::var("TENANT_STATS_ENABLED")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(true),
+                interval_secs: env::var("TENANT_STATS_INTERVAL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(60),
+                timeout_secs: env::var("TENANT_STATS_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(10),
+                concurrency: env::var("TENANT_STATS_CONCURRENCY")
+                    .ok()
+                    .and_then(|c| c.parse().ok())
+                    .unwrap_or(8),
+                pool_size: env::var("TENANT_STATS_POOL_SIZE")
+                    .ok()
+                    .and_then(|c| c.parse().ok())
+                    .filter(|c| *c > 0)
+                    .unwrap_or(1),
+            },
+            quota: QuotaConfig {
+                enabled: env::var("QUOTA_ENABLED")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(true),
+                interval_secs: env::var("QUOTA_INTERVAL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(60),
+            },
+            metering: MeteringConfig {
+                enabled: env::var("METERING_ENABLED")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(true),
+                interval_secs: env::var("METERING_INTERVAL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(60),
+                daily_retention_days: env::var("METERING_DAILY_RETENTION_DAYS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(400),
+            },
+            idempotency: IdempotencyConfig {
+                ttl_secs: env::var("IDEMPOTENCY_TTL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(86400),
+                in_flight_timeout_secs: env::var("IDEMPOTENCY_IN_FLIGHT_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s >= 3)
+                    .unwrap_or(60),
+            },
+            webhooks: WebhookConfig {
+                enabled: env::var("WEBHOOKS_ENABLED")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(true),
+                poll_interval_secs: env::var("WEBHOOK_POLL_INTERVAL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(5),
+                timeout_secs: env::var("WEBHOOK_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(10),
+                max_attempts: env::var("WEBHOOK_MAX_ATTEMPTS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(8),
+                retry_base_secs: env::var("WEBHOOK_RETRY_BASE_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(30),
+                retention_days: env::var("WEBHOOK_RETENTION_DAYS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(30),
+                allowed_networks: env::var("WEBHOOK_ALLOWED_NETWORKS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|network| network.trim().to_string())
+                    .filter(|network| !network.is_empty())
+                    .collect(),
+            },
+            schedules: ScheduleConfig {
+                enabled: env::var("SCHEDULER_ENABLED")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(true),
+                poll_interval_secs: env::var("SCHEDULER_POLL_INTERVAL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(15),
+                misfire_grace_secs: env::var("SCHEDULE_MISFIRE_GRACE_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(3600),
+            },
+            idle: IdleConfig {
+                enabled: env::var("AUTO_IDLE_ENABLED")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(true),
+                interval_secs: env::var("AUTO_IDLE_INTERVAL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(300),
+            },
+            provisioner: ProvisionerConfig {
+                backend: env::var("PROVISIONER")
+                    .map(|b| b.trim().to_lowercase())
+                    .ok()
+                    .filter(|b| !b.is_empty())
+                    .unwrap_or_else(|| "noop".to_string()),
+                postgres_url: env::var("PROVISIONER_POSTGRES_URL")
+                    .ok()
+                    .filter(|u| !u.is_empty()),
+                http_url: env::var("PROVISIONER_HTTP_URL")
+                    .ok()
+                    .filter(|u| !u.is_empty()),
+                http_token: env::var("PROVISIONER_HTTP_TOKEN")
+                    .ok()
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty()),
+                timeout_secs: env::var("PROVISIONER_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(30),
+            },
+            enforcement: EnforcementConfig {
+                enabled: env::var("SUSPEND_ENFORCEMENT_ENABLED")
+                    .ok()
+                    .and_then(|e| e.parse().ok())
+                    .unwrap_or(false),
+                revoke_login_roles: env::var("SUSPEND_REVOKE_LOGIN_ROLES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|role| role.trim().to_string())
+                    .filter(|role| !role.is_empty())
+                    .collect(),
+                maintenance_database: env::var("SUSPEND_ENFORCEMENT_MAINTENANCE_DATABASE")
+                    .ok()
+                    .map(|d| d.trim().to_string())
+                    .filter(|d| !d.is_empty())
+                    .unwrap_or_else(|| "postgres".to_string()),
+                timeout_secs: env::var("SUSPEND_ENFORCEMENT_TIMEOUT_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(10),
+            },
+            jobs: JobConfig {
+                workers: env::var("JOB_WORKERS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(4),
+                poll_interval_secs: env::var("JOB_POLL_INTERVAL_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(5),
+                max_attempts: env::var("JOB_MAX_ATTEMPTS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(5),
+                retry_base_secs: env::var("JOB_RETRY_BASE_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(15),
+                lease_secs: env::var("JOB_LEASE_SECONDS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(300),
+                retention_days: env::var("JOB_RETENTION_DAYS")
+                    .ok()
+                    .and_then(|s| s.parse().ok())
+                    .filter(|s| *s > 0)
+                    .unwrap_or(7),
             },
         }
     }
@@ -0,0 +1,193 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use crate::auth::Identity;
+use crate::net::IpNetworks;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Who made a request and where it came from, as recorded in the audit log.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    /// Caller identity: the API key name (`key:<name>`), or when authentication
+    /// is disabled the `X-Actor` header (defaults to `api`)
+    pub actor: String,
+    /// Request ID assigned by `request_id_middleware` or supplied by the client
+    pub request_id: String,
+    /// The peer address or, when the peer is one of the [`TrustedProxies`], the
+    /// right-most `X-Forwarded-For` hop that is not (the app runs behind a proxy
+    /// on Railway/Render)
+    pub source_ip: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for RequestContext
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let actor = match parts.extensions.get::<Identity>() {
+            Some(identity) => identity.name.clone(),
+            None => header_str(&parts.headers, "x-actor")
+                .unwrap_or("api")
+                .to_string(),
+        };
+        let request_id = header_str(&parts.headers, REQUEST_ID_HEADER)
+            .unwrap_or_default()
+            .to_string();
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let source_ip = match parts.extensions.get::<Arc<TrustedProxies>>() {
+            Some(proxies) => proxies.client_ip(peer, header_str(&parts.headers, "x-forwarded-for")),
+            None => peer,
+        }
+        .map(|ip| ip.to_string());
+
+        Ok(Self {
+            actor,
+            request_id,
+            source_ip,
+        })
+    }
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+}
+
+/// Proxies whose `X-Forwarded-For` hops are believed, from `TRUSTED_PROXIES`.
+/// Clients can send any `X-Forwarded-For`, so hops are only taken from the
+/// right, as far as each was added by a trusted proxy.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: IpNetworks,
+}
+
+impl TrustedProxies {
+    /// Parses IP addresses and CIDR networks such as `10.0.0.0/8`.
+    pub fn parse(entries: &[String]) -> anyhow::Result<Self> {
+        let networks = IpNetworks::parse(entries)
+            .map_err(|entry| anyhow::anyhow!("invalid trusted proxy '{}'", entry))?;
+        Ok(Self { networks })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.networks.contains(ip)
+    }
+
+    /// The caller's address: `peer`, unless it is a trusted proxy, in which
+    /// case the right-most hop of `forwarded_for` that is not. Hops that do not
+    /// parse end the walk, as does running out of hops.
+    pub fn client_ip(&self, peer: Option<IpAddr>, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        let mut client = peer?;
+        if !self.contains(client) {
+            return Some(client);
+        }
+        for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+            let hop = hop.trim();
+            let Some(ip) = hop
+                .parse::<IpAddr>()
+                .ok()
+                .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+            else {
+                break;
+            };
+            client = ip;
+            if !self.contains(ip) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(entries: &[&str]) -> TrustedProxies {
+        let entries: Vec<String> = entries.iter().map(|e| e.to_string()).collect();
+        TrustedProxies::parse(&entries).unwrap()
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn ignores_forwarded_for_from_untrusted_peers() {
+        let none = TrustedProxies::default();
+        assert_eq!(
+            none.client_ip(ip("203.0.113.7"), Some("198.51.100.1")),
+            ip("203.0.113.7")
+        );
+
+        let trusted = proxies(&["10.0.0.0/8"]);
+        assert_eq!(
+            trusted.client_ip(ip("203.0.113.7"), Some("198.51.100.1")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(trusted.client_ip(None, Some("198.51.100.1")), None);
+    }
+
+    #[test]
+    fn takes_the_right_most_untrusted_hop() {
+        let trusted = proxies(&["10.0.0.0/8", "192.0.2.1"]);
+
+        // The client prepended a forged hop, which is never reached
+        assert_eq!(
+            trusted.client_ip(ip("10.1.2.3"), Some("1.2.3.4, 203.0.113.7, 192.0.2.1")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            trusted.client_ip(ip("10.1.2.3"), Some("203.0.113.7:4711")),
+            ip("203.0.113.7")
+        );
+        assert_eq!(trusted.client_ip(ip("10.1.2.3"), None), ip("10.1.2.3"));
+        assert_eq!(
+            trusted.client_ip(ip("10.1.2.3"), Some("garbage, 10.4.4.4")),
+            ip("10.4.4.4")
+        );
+    }
+
+    #[test]
+    fn matches_networks() {
+        let trusted = proxies(&["172.16.0.0/12", "fd00::/8", "::ffff:127.0.0.1"]);
+
+        assert!(trusted.contains("172.31.255.255".parse().unwrap()));
+        assert!(!trusted.contains("172.32.0.1".parse().unwrap()));
+        assert!(trusted.contains("fd12::1".parse().unwrap()));
+        assert!(trusted.contains("127.0.0.1".parse().unwrap()));
+        assert!(trusted.contains("::ffff:172.16.0.1".parse().unwrap()));
+        assert!(proxies(&["0.0.0.0/0"]).contains("8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn rejects_invalid_proxies() {
+        for entry in ["proxy.internal", "10.0.0.0/33", "10.0.0.0/x", "::/129"] {
+            assert!(
+                TrustedProxies::parse(&[entry.to_string()]).is_err(),
+                "{}",
+                entry
+            );
+        }
+    }
+}
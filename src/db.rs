@@ -2,13 +2,22 @@ use sqlx::{postgres::PgPoolOptions, PgPool};
 use std::time::Duration;
 use tracing::info;
 
+use crate::crypto::{is_encrypted, Keyring};
+use crate::platform::{ProjectStatus, Region};
+
 pub struct Database {
     pub pool: PgPool,
     pub max_connections: u32,
+    /// Master keys for secrets stored in Postgres; `None` stores them in plaintext
+    pub keyring: Option<Keyring>,
 }
 
 impl Database {
-    pub async fn new(database_url: &str, max_connections: u32) -> anyhow::Result<Self> {
+    pub async fn new(
+        database_url: &str,
+        max_connections: u32,
+        keyring: Option<Keyring>,
+    ) -> anyhow::Result<Self> {
         // Normalize database URL - Railway might provide it without protocol prefix
         let normalized_url = if !database_url.starts_with("postgresql://") && !database_url.starts_with("postgres://") {
             // If it doesn't start with protocol, try adding postgresql://
@@ -35,10 +44,33 @@ impl Database {
         // Initialize schema
         Self::init_schema(&pool).await?;
 
-        Ok(Self {
+        let database = Self {
             pool,
             max_connections,
-        })
+            keyring,
+        };
+        database.secure_project_db_urls().await?;
+
+        Ok(database)
+    }
+
+    /// Encrypts `plaintext` for storage if a keyring is configured.
+    pub fn seal_secret(&self, plaintext: &str, context: &str) -> anyhow::Result<String> {
+        match &self.keyring {
+            Some(keyring) => keyring.encrypt(plaintext, context),
+            None => Ok(plaintext.to_string()),
+        }
+    }
+
+    /// Reverses [`Database::seal_secret`].
+    pub fn open_secret(&self, stored: &str, context: &str) -> anyhow::Result<String> {
+        match &self.keyring {
+            Some(keyring) => keyring.decrypt(stored, context),
+            None if is_encrypted(stored) => {
+                anyhow::bail!("Value is encrypted but no ENCRYPTION_KEYS are configured")
+            }
+            None => Ok(stored.to_string()),
+        }
     }
 
     pub fn get_pool_stats(&self) -> (u32, u32) {
@@ -50,6 +82,55 @@ impl Database {
         (size, size) // Return (size, max_connections) - active will be tracked via query metrics
     }
 
+    /// Normalizes spellings of values in `allowed` in `column` of
+    /// `platform_projects`, such as `US_East_1` for `us-east-1`.
+    async fn normalize_enum_column(
+        pool: &PgPool,
+        column: &str,
+        allowed: &[&str],
+    ) -> anyhow::Result<()> {
+        sqlx::query(&format!(
+            r#"
+            UPDATE platform_projects
+            SET {column} = LOWER(REGEXP_REPLACE(TRIM({column}), '[_ ]+', '-', 'g'))
+            WHERE {column} <> ALL($1)
+              AND LOWER(REGEXP_REPLACE(TRIM({column}), '[_ ]+', '-', 'g')) = ANY($1)
+            "#
+        ))
+        .bind(allowed)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Projects whose `column` holds a value not in `allowed`, described as
+    /// `project <id> (<slug>): <column> '<value>'`.
+    async fn unsupported_values(
+        pool: &PgPool,
+        column: &str,
+        allowed: &[&str],
+    ) -> anyhow::Result<Vec<String>> {
+        let rows: Vec<(i64, String, String)> = sqlx::query_as(&format!(
+            r#"
+            SELECT id, slug, {column}::TEXT
+            FROM platform_projects
+            WHERE {column} <> ALL($1)
+            ORDER BY id
+            "#
+        ))
+        .bind(allowed)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(id, slug, value)| {
+                format!("project {} ({}): {} '{}'", id, slug, column, value)
+            })
+            .collect())
+    }
+
     async fn init_schema(pool: &PgPool) -> anyhow::Result<()> {
         sqlx::query(
             r#"
@@ -87,7 +168,7 @@ impl Database {
             CREATE TABLE IF NOT EXISTS platform_projects (
                 id BIGSERIAL PRIMARY KEY,
                 name VARCHAR(255) NOT NULL,
-                slug VARCHAR(255) NOT NULL UNIQUE,
+                slug VARCHAR(255) NOT NULL,
                 status VARCHAR(50) NOT NULL DEFAULT 'active',
                 plan VARCHAR(50) NOT NULL DEFAULT 'dev',
                 region VARCHAR(100) NOT NULL,
@@ -100,6 +181,678 @@ impl Database {
         .execute(pool)
         .await?;
 
+        // Deleted projects are kept, so only live projects hold on to their slug
+        sqlx::query(
+            "ALTER TABLE platform_projects DROP CONSTRAINT IF EXISTS platform_projects_slug_key",
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE UNIQUE INDEX IF NOT EXISTS idx_platform_projects_live_slug
+            ON platform_projects (slug) WHERE status <> 'deleted'
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Status and region were free-form before they were typed: spellings of
+        // known values are normalized, and anything else must be fixed by hand,
+        // as such rows could not be read. Nothing is guessed, since moving a
+        // project to another region or status would misplace it
+        let statuses: Vec<&str> = ProjectStatus::ALL.iter().map(ProjectStatus::as_str).collect();
+        let regions: Vec<&str> = Region::ALL.iter().map(Region::as_str).collect();
+        Self::normalize_enum_column(pool, "status", &statuses).await?;
+        Self::normalize_enum_column(pool, "region", &regions).await?;
+        let mut unsupported = Self::unsupported_values(pool, "status", &statuses).await?;
+        unsupported.extend(Self::unsupported_values(pool, "region", &regions).await?);
+        if !unsupported.is_empty() {
+            anyhow::bail!(
+                "platform_projects holds unsupported values; correct them before starting. \
+                 Supported statuses: {}. Supported regions: {}. Found: {}",
+                statuses.join(", "),
+                regions.join(", "),
+                unsupported.join("; ")
+            );
+        }
+
+        // db_url holds the (encrypted) connection string; db_url_redacted is what the API shows
+        sqlx::query("ALTER TABLE platform_projects ADD COLUMN IF NOT EXISTS db_url_redacted TEXT")
+            .execute(pool)
+            .await?;
+
+        // version and updated_at back the ETag of a project; every update of what the
+        // API shows bumps them, so writers can detect that someone else changed the
+        // project in between. Internal writes, such as re-encrypting the connection
+        // string under a new key, leave them alone so that clients' ETags stay valid
+        sqlx::query(
+            r#"
+            DO $$
+            BEGIN
+                IF NOT EXISTS (
+                    SELECT 1 FROM information_schema.columns
+                    WHERE table_name = 'platform_projects' AND column_name = 'updated_at'
+                ) THEN
+                    ALTER TABLE platform_projects
+                        ADD COLUMN version BIGINT NOT NULL DEFAULT 1,
+                        ADD COLUMN updated_at TIMESTAMP WITH TIME ZONE;
+                    UPDATE platform_projects SET updated_at = created_at;
+                    ALTER TABLE platform_projects
+                        ALTER COLUMN updated_at SET DEFAULT CURRENT_TIMESTAMP,
+                        ALTER COLUMN updated_at SET NOT NULL;
+                END IF;
+            END
+            $$
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE OR REPLACE FUNCTION platform_projects_bump_version() RETURNS trigger AS $$
+            BEGIN
+                IF (NEW.organization_id, NEW.name, NEW.slug, NEW.status, NEW.plan, NEW.region,
+                    NEW.db_url_redacted, NEW.api_base_url, NEW.labels)
+                   IS DISTINCT FROM
+                   (OLD.organization_id, OLD.name, OLD.slug, OLD.status, OLD.plan, OLD.region,
+                    OLD.db_url_redacted, OLD.api_base_url, OLD.labels) THEN
+                    NEW.version := OLD.version + 1;
+                    NEW.updated_at := NOW();
+                END IF;
+                RETURN NEW;
+            END;
+            $$ LANGUAGE plpgsql
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE OR REPLACE TRIGGER platform_projects_bump_version
+            BEFORE UPDATE ON platform_projects
+            FOR EACH ROW EXECUTE FUNCTION platform_projects_bump_version()
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Free-form key/value labels, matched with label selectors
+        sqlx::query(
+            "ALTER TABLE platform_projects ADD COLUMN IF NOT EXISTS labels JSONB NOT NULL DEFAULT '{}'",
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_platform_projects_labels ON platform_projects USING GIN (labels)",
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS organizations (
+                id BIGSERIAL PRIMARY KEY,
+                name VARCHAR(255) NOT NULL,
+                slug VARCHAR(63) NOT NULL UNIQUE,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS organization_members (
+                organization_id BIGINT NOT NULL REFERENCES organizations(id) ON DELETE CASCADE,
+                member VARCHAR(100) NOT NULL,
+                role VARCHAR(20) NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (organization_id, member)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_organization_members_member ON organization_members (member)",
+        )
+        .execute(pool)
+        .await?;
+
+        // Every project belongs to one organization; projects created before
+        // organizations existed are moved into a "default" one
+        sqlx::query(
+            r#"
+            ALTER TABLE platform_projects
+            ADD COLUMN IF NOT EXISTS organization_id BIGINT REFERENCES organizations(id)
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO organizations (name, slug)
+            SELECT 'Default', 'default'
+            WHERE EXISTS (SELECT 1 FROM platform_projects WHERE organization_id IS NULL)
+            ON CONFLICT (slug) DO NOTHING
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE platform_projects
+            SET organization_id = (SELECT id FROM organizations WHERE slug = 'default')
+            WHERE organization_id IS NULL
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query("ALTER TABLE platform_projects ALTER COLUMN organization_id SET NOT NULL")
+            .execute(pool)
+            .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_platform_projects_organization ON platform_projects (organization_id)",
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS platform_project_events (
+                id BIGSERIAL PRIMARY KEY,
+                project_id BIGINT NOT NULL REFERENCES platform_projects(id) ON DELETE CASCADE,
+                from_status VARCHAR(50),
+                to_status VARCHAR(50) NOT NULL,
+                actor VARCHAR(255) NOT NULL,
+                reason TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS platform_project_events_project_id_idx
+            ON platform_project_events (project_id, id)
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS audit_log (
+                id BIGSERIAL PRIMARY KEY,
+                actor VARCHAR(255) NOT NULL,
+                request_id VARCHAR(255) NOT NULL,
+                source_ip VARCHAR(64),
+                action VARCHAR(100) NOT NULL,
+                resource_type VARCHAR(100) NOT NULL,
+                resource_id VARCHAR(255),
+                before JSONB,
+                after JSONB,
+                diff JSONB NOT NULL DEFAULT '{}',
+                outcome VARCHAR(20) NOT NULL,
+                status_code INTEGER NOT NULL,
+                error TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL,
+                prev_hash CHAR(64) NOT NULL,
+                hash CHAR(64) NOT NULL UNIQUE
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS audit_log_resource_idx
+            ON audit_log (resource_type, resource_id)
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // The audit log is append-only; the hash chain detects tampering by anyone
+        // who bypasses this trigger with superuser access
+        sqlx::query(
+            r#"
+            CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+            BEGIN
+                RAISE EXCEPTION 'audit_log is append-only';
+            END;
+            $$ LANGUAGE plpgsql
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE OR REPLACE TRIGGER audit_log_append_only
+            BEFORE UPDATE OR DELETE ON audit_log
+            FOR EACH ROW EXECUTE FUNCTION audit_log_append_only()
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS api_keys (
+                id BIGSERIAL PRIMARY KEY,
+                name VARCHAR(100) NOT NULL,
+                prefix VARCHAR(16) NOT NULL,
+                key_hash CHAR(64) NOT NULL UNIQUE,
+                scopes TEXT[] NOT NULL,
+                created_by TEXT NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                expires_at TIMESTAMP WITH TIME ZONE,
+                last_used_at TIMESTAMP WITH TIME ZONE,
+                revoked_at TIMESTAMP WITH TIME ZONE
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS platform_project_probes (
+                id BIGSERIAL PRIMARY KEY,
+                project_id BIGINT NOT NULL REFERENCES platform_projects(id) ON DELETE CASCADE,
+                up BOOLEAN NOT NULL,
+                db_up BOOLEAN NOT NULL,
+                db_latency_ms DOUBLE PRECISION NOT NULL,
+                db_error TEXT,
+                api_up BOOLEAN NOT NULL,
+                api_latency_ms DOUBLE PRECISION NOT NULL,
+                api_status INTEGER,
+                api_error TEXT,
+                probed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_platform_project_probes_project ON platform_project_probes (project_id, probed_at DESC)",
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_platform_project_probes_probed_at ON platform_project_probes (probed_at)",
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS plans (
+                name VARCHAR(63) PRIMARY KEY,
+                display_name VARCHAR(255) NOT NULL,
+                max_db_size_bytes BIGINT,
+                max_connections INTEGER,
+                monthly_request_budget BIGINT,
+                retention_days INTEGER NOT NULL,
+                features TEXT[] NOT NULL DEFAULT '{}',
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Hours without activity after which projects on the plan are suspended;
+        // NULL leaves them running
+        sqlx::query("ALTER TABLE plans ADD COLUMN IF NOT EXISTS idle_suspend_after_hours INTEGER")
+            .execute(pool)
+            .await?;
+
+        // The plans that used to be hard-coded, plus any other value already in use,
+        // so the foreign key below can be added to existing databases
+        sqlx::query(
+            r#"
+            INSERT INTO plans (name, display_name, max_db_size_bytes, max_connections,
+                               monthly_request_budget, retention_days, features)
+            VALUES
+                ('dev', 'Development', 524288000, 60, 500000, 1, '{}'),
+                ('pro', 'Professional', 8589934592, 200, 5000000, 7,
+                 '{daily_backups,custom_domains}'),
+                ('enterprise', 'Enterprise', NULL, NULL, NULL, 30,
+                 '{daily_backups,custom_domains,point_in_time_recovery,sso}')
+            ON CONFLICT (name) DO NOTHING
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO plans (name, display_name, retention_days)
+            SELECT DISTINCT plan, plan, 7 FROM platform_projects
+            ON CONFLICT (name) DO NOTHING
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DO $$
+            BEGIN
+                IF NOT EXISTS (
+                    SELECT 1 FROM pg_constraint WHERE conname = 'platform_projects_plan_fkey'
+                ) THEN
+                    ALTER TABLE platform_projects
+                    ADD CONSTRAINT platform_projects_plan_fkey
+                    FOREIGN KEY (plan) REFERENCES plans(name);
+                END IF;
+            END
+            $$
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Latest usage collected from each project database, for quota evaluation
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS platform_project_usage (
+                project_id BIGINT PRIMARY KEY REFERENCES platform_projects(id) ON DELETE CASCADE,
+                db_size_bytes BIGINT NOT NULL,
+                connections INTEGER NOT NULL,
+                collected_at TIMESTAMP WITH TIME ZONE NOT NULL
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Activity counters for idle detection: the last value of the table access
+        // counter, and when activity was last seen
+        sqlx::query(
+            r#"
+            ALTER TABLE platform_project_usage
+                ADD COLUMN IF NOT EXISTS table_activity BIGINT,
+                ADD COLUMN IF NOT EXISTS active_connections INTEGER,
+                ADD COLUMN IF NOT EXISTS last_active_at TIMESTAMP WITH TIME ZONE
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS platform_project_quotas (
+                project_id BIGINT NOT NULL REFERENCES platform_projects(id) ON DELETE CASCADE,
+                resource VARCHAR(50) NOT NULL,
+                plan VARCHAR(63) NOT NULL,
+                used BIGINT NOT NULL,
+                quota BIGINT NOT NULL,
+                utilization DOUBLE PRECISION NOT NULL,
+                exceeded BOOLEAN NOT NULL,
+                exceeded_since TIMESTAMP WITH TIME ZONE,
+                measured_at TIMESTAMP WITH TIME ZONE NOT NULL,
+                evaluated_at TIMESTAMP WITH TIME ZONE NOT NULL,
+                PRIMARY KEY (project_id, resource)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Metered usage per project, plan and UTC hour or day
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS usage_records (
+                project_id BIGINT NOT NULL REFERENCES platform_projects(id) ON DELETE CASCADE,
+                plan VARCHAR(63) NOT NULL,
+                granularity VARCHAR(10) NOT NULL,
+                period_start TIMESTAMP WITH TIME ZONE NOT NULL,
+                samples INTEGER NOT NULL DEFAULT 0,
+                active_hours DOUBLE PRECISION NOT NULL DEFAULT 0,
+                suspended_hours DOUBLE PRECISION NOT NULL DEFAULT 0,
+                connection_hours DOUBLE PRECISION NOT NULL DEFAULT 0,
+                db_size_bytes_max BIGINT,
+                api_requests BIGINT NOT NULL DEFAULT 0,
+                updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                PRIMARY KEY (project_id, granularity, period_start, plan)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS usage_records_period_idx
+            ON usage_records (granularity, period_start)
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // Responses to requests sent with an Idempotency-Key, per caller and key.
+        // status_code is NULL while the first request is still being handled
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS idempotency_keys (
+                principal VARCHAR(255) NOT NULL,
+                key VARCHAR(255) NOT NULL,
+                request_hash VARCHAR(64) NOT NULL,
+                status_code SMALLINT,
+                response_headers JSONB,
+                response_body BYTEA,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+                PRIMARY KEY (principal, key)
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // A request holds its key under claim_token until locked_until, which it
+        // keeps pushing back while it runs; only the holder may store its response
+        sqlx::query(
+            r#"
+            ALTER TABLE idempotency_keys
+                ADD COLUMN IF NOT EXISTS claim_token VARCHAR(36),
+                ADD COLUMN IF NOT EXISTS locked_until TIMESTAMP WITH TIME ZONE
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        // secret is encrypted like project database URLs; an empty event_types
+        // array subscribes to every event
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS webhook_subscriptions (
+                id BIGSERIAL PRIMARY KEY,
+                url TEXT NOT NULL,
+                description VARCHAR(255),
+                event_types TEXT[] NOT NULL DEFAULT '{}',
+                secret TEXT NOT NULL,
+                active BOOLEAN NOT NULL DEFAULT TRUE,
+                created_by VARCHAR(255) NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id BIGSERIAL PRIMARY KEY,
+                subscription_id BIGINT NOT NULL REFERENCES webhook_subscriptions(id) ON DELETE CASCADE,
+                event_id VARCHAR(64) NOT NULL,
+                event_type VARCHAR(100) NOT NULL,
+                payload JSONB NOT NULL,
+                status VARCHAR(20) NOT NULL DEFAULT 'pending',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                last_attempt_at TIMESTAMP WITH TIME ZONE,
+                response_status INTEGER,
+                response_body TEXT,
+                last_error TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                delivered_at TIMESTAMP WITH TIME ZONE
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS webhook_deliveries_subscription_idx
+            ON webhook_deliveries (subscription_id, id)
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS webhook_deliveries_due_idx
+            ON webhook_deliveries (next_attempt_at) WHERE status = 'pending'
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS project_schedules (
+                id BIGSERIAL PRIMARY KEY,
+                project_id BIGINT NOT NULL REFERENCES platform_projects(id) ON DELETE CASCADE,
+                action VARCHAR(20) NOT NULL,
+                cron VARCHAR(255),
+                run_at TIMESTAMP WITH TIME ZONE,
+                timezone VARCHAR(64) NOT NULL DEFAULT 'UTC',
+                reason VARCHAR(255),
+                enabled BOOLEAN NOT NULL DEFAULT TRUE,
+                next_run_at TIMESTAMP WITH TIME ZONE,
+                last_run_at TIMESTAMP WITH TIME ZONE,
+                last_outcome VARCHAR(20),
+                last_error TEXT,
+                created_by VARCHAR(255) NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                CHECK ((cron IS NULL) <> (run_at IS NULL))
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS project_schedules_project_idx
+            ON project_schedules (project_id, id)
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS project_schedules_due_idx
+            ON project_schedules (next_run_at) WHERE enabled
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS platform_project_enforcement_steps (
+                id BIGSERIAL PRIMARY KEY,
+                project_id BIGINT NOT NULL REFERENCES platform_projects(id) ON DELETE CASCADE,
+                action VARCHAR(20) NOT NULL,
+                step VARCHAR(30) NOT NULL,
+                role VARCHAR(255),
+                succeeded BOOLEAN NOT NULL,
+                detail TEXT,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS platform_project_enforcement_steps_project_idx
+            ON platform_project_enforcement_steps (project_id, id)
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS jobs (
+                id BIGSERIAL PRIMARY KEY,
+                kind VARCHAR(50) NOT NULL,
+                status VARCHAR(20) NOT NULL DEFAULT 'queued',
+                project_id BIGINT REFERENCES platform_projects(id) ON DELETE CASCADE,
+                payload JSONB NOT NULL DEFAULT '{}',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                max_attempts INTEGER NOT NULL,
+                run_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                locked_until TIMESTAMP WITH TIME ZONE,
+                last_error TEXT,
+                result JSONB,
+                created_by VARCHAR(255) NOT NULL,
+                created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                started_at TIMESTAMP WITH TIME ZONE,
+                finished_at TIMESTAMP WITH TIME ZONE
+            )
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS jobs_due_idx
+            ON jobs (run_at) WHERE status IN ('queued', 'running')
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE INDEX IF NOT EXISTS jobs_project_idx
+            ON jobs (project_id, id)
+            "#,
+        )
+        .execute(pool)
+        .await?;
+
         info!("Database schema initialized");
         Ok(())
     }
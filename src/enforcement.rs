@@ -0,0 +1,488 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgConnectOptions, PgConnection};
+use sqlx::{ConnectOptions, Connection, FromRow};
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::EnforcementConfig;
+use crate::db::Database;
+use crate::metrics::Metrics;
+use crate::platform::{string_enum, PlatformProject, ProjectStatus};
+use crate::provisioner::{quote_ident, ProjectProvisioner, ProvisionAction};
+
+/// First key of the advisory locks serializing enforcement per project; the
+/// second is the project ID.
+const ENFORCEMENT_LOCK_CLASS: i32 = 0x656e_6672;
+
+string_enum! {
+    /// Transition an enforcement step was taken for
+    pub enum EnforcementAction {
+        Suspend => "suspend",
+        Resume => "resume",
+    }
+}
+
+string_enum! {
+    /// What an enforcement step does on the project's Postgres
+    pub enum EnforcementStepKind {
+        Connect => "connect",
+        BlockConnections => "block_connections",
+        RevokeLogin => "revoke_login",
+        TerminateBackends => "terminate_backends",
+        GrantLogin => "grant_login",
+        AllowConnections => "allow_connections",
+    }
+}
+
+/// The result of one step of blocking or restoring access to a project's database.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct EnforcementStep {
+    pub id: i64,
+    pub project_id: i64,
+    pub action: EnforcementAction,
+    pub step: EnforcementStepKind,
+    /// Role whose LOGIN was changed, for `revoke_login` and `grant_login`
+    #[schema(example = "app_user")]
+    pub role: Option<String>,
+    pub succeeded: bool,
+    /// What the step did, or why it failed
+    #[schema(example = "terminated 3 sessions")]
+    pub detail: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Query parameters for listing enforcement steps.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EnforcementQuery {
+    /// Maximum number of steps to return, newest first (default 50, max 1000)
+    pub limit: Option<i64>,
+}
+
+impl EnforcementQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50).clamp(1, 1000)
+    }
+}
+
+impl EnforcementAction {
+    /// Status a project must still have for the action to be enforced.
+    fn status(self) -> ProjectStatus {
+        match self {
+            EnforcementAction::Suspend => ProjectStatus::Suspended,
+            EnforcementAction::Resume => ProjectStatus::Active,
+        }
+    }
+}
+
+/// A step taken, before it is recorded.
+struct StepResult {
+    step: EnforcementStepKind,
+    role: Option<String>,
+    /// What the step did, or why it failed
+    outcome: Result<Option<String>, String>,
+}
+
+/// Wraps a provisioner to also block access to a suspended project's database,
+/// as the provisioner's administrator or through the project's stored
+/// `db_url`: connecting is disallowed, the
+/// configured roles lose LOGIN and open sessions are terminated. Resuming
+/// reverses this.
+///
+/// Enforcement runs once the transition has been recorded, so a transition
+/// that fails or loses a race never leaves a database blocked. Enforcement of a
+/// project is serialized and skipped if the project has moved on meanwhile, so
+/// the latest transition wins. Only roles that cannot connect to any other
+/// database of the server have their LOGIN changed, as roles are shared by the
+/// whole server.
+///
+/// Enforcement never fails a transition. Every step is recorded in
+/// `platform_project_enforcement_steps`, so that partial failures can be seen
+/// and fixed by hand.
+pub struct EnforcingProvisioner {
+    inner: Arc<dyn ProjectProvisioner>,
+    db: Arc<Database>,
+    metrics: Arc<Metrics>,
+    config: EnforcementConfig,
+}
+
+impl EnforcingProvisioner {
+    pub fn wrap(
+        inner: Arc<dyn ProjectProvisioner>,
+        db: Arc<Database>,
+        metrics: Arc<Metrics>,
+        config: EnforcementConfig,
+    ) -> Arc<dyn ProjectProvisioner> {
+        Arc::new(Self {
+            inner,
+            db,
+            metrics,
+            config,
+        })
+    }
+
+    async fn enforce(&self, project: &PlatformProject, action: EnforcementAction) {
+        let lock = match self.db.lock_enforcement(project.id).await {
+            Ok((lock, status)) if status == Some(action.status()) => lock,
+            Ok((_, status)) => {
+                tracing::info!(
+                    "Skipping {} enforcement of project {}: it is now {}",
+                    action,
+                    project.slug,
+                    status.map_or("gone".to_string(), |status| status.to_string())
+                );
+                return;
+            }
+            Err(e) => {
+                tracing::error!(
+                    "Failed to lock {} enforcement of project {}: {}",
+                    action,
+                    project.slug,
+                    e
+                );
+                return;
+            }
+        };
+
+        let steps = self.run_steps(project, action).await;
+        for step in &steps {
+            let outcome = if step.outcome.is_ok() {
+                "succeeded"
+            } else {
+                "failed"
+            };
+            self.metrics
+                .project_enforcement_steps_total
+                .with_label_values(&[action.as_str(), step.step.as_str(), outcome])
+                .inc();
+            if let Err(e) = &step.outcome {
+                tracing::warn!(
+                    "Step {} of {} enforcement failed for project {}: {}",
+                    step.step,
+                    action,
+                    project.slug,
+                    e
+                );
+            }
+        }
+
+        if let Err(e) = self
+            .db
+            .record_enforcement_steps(project.id, action, &steps)
+            .await
+        {
+            tracing::error!(
+                "Failed to record {} enforcement of project {}: {}",
+                action,
+                project.slug,
+                e
+            );
+        }
+        if let Err(e) = lock.close().await {
+            tracing::warn!(
+                "Failed to release {} enforcement lock of project {}: {}",
+                action,
+                project.slug,
+                e
+            );
+        }
+    }
+
+    async fn run_steps(
+        &self,
+        project: &PlatformProject,
+        action: EnforcementAction,
+    ) -> Vec<StepResult> {
+        let (mut connection, database, user) = match self.connect(project).await {
+            Ok(connected) => connected,
+            Err(e) => {
+                return vec![StepResult {
+                    step: EnforcementStepKind::Connect,
+                    role: None,
+                    outcome: Err(e),
+                }]
+            }
+        };
+        let quoted = quote_ident(&database);
+        let mut steps = Vec::new();
+
+        match action {
+            EnforcementAction::Suspend => {
+                let sql = format!("ALTER DATABASE {quoted} ALLOW_CONNECTIONS false");
+                steps.push(StepResult {
+                    step: EnforcementStepKind::BlockConnections,
+                    role: None,
+                    outcome: self.execute(&mut connection, &sql).await,
+                });
+                for role in &self.config.revoke_login_roles {
+                    let outcome = if *role == user {
+                        Err("TelemetryWatch connects as this role; its LOGIN is kept".to_string())
+                    } else {
+                        self.set_login(&mut connection, role, &database, false)
+                            .await
+                    };
+                    steps.push(StepResult {
+                        step: EnforcementStepKind::RevokeLogin,
+                        role: Some(role.clone()),
+                        outcome,
+                    });
+                }
+                // Last, so that no session opened meanwhile survives
+                let outcome = self
+                    .step(
+                        sqlx::query_scalar::<_, i64>(
+                            r#"
+                            SELECT COUNT(*) FILTER (WHERE pg_terminate_backend(pid))
+                            FROM pg_stat_activity
+                            WHERE datname = $1 AND pid <> pg_backend_pid()
+                            "#,
+                        )
+                        .bind(&database)
+                        .fetch_one(&mut connection),
+                    )
+                    .await
+                    .map(|count| Some(format!("terminated {} sessions", count)));
+                steps.push(StepResult {
+                    step: EnforcementStepKind::TerminateBackends,
+                    role: None,
+                    outcome,
+                });
+            }
+            EnforcementAction::Resume => {
+                for role in &self.config.revoke_login_roles {
+                    if *role == user {
+                        continue;
+                    }
+                    steps.push(StepResult {
+                        step: EnforcementStepKind::GrantLogin,
+                        role: Some(role.clone()),
+                        outcome: self.set_login(&mut connection, role, &database, true).await,
+                    });
+                }
+                let sql = format!("ALTER DATABASE {quoted} ALLOW_CONNECTIONS true");
+                steps.push(StepResult {
+                    step: EnforcementStepKind::AllowConnections,
+                    role: None,
+                    outcome: self.execute(&mut connection, &sql).await,
+                });
+            }
+        }
+
+        let _ = connection.close().await;
+        steps
+    }
+
+    /// Connects to the maintenance database, as the project database refuses
+    /// connections while the project is suspended. The provisioner's
+    /// administrator is used if it has one, as it may have taken away the
+    /// LOGIN of the project's own role; otherwise the project's credentials.
+    /// Returns the connection, the project database's name and the user.
+    async fn connect(
+        &self,
+        project: &PlatformProject,
+    ) -> Result<(PgConnection, String, String), String> {
+        let options = match self.inner.admin_connect_options(project) {
+            Some(options) => options,
+            None => {
+                let url = self
+                    .db
+                    .project_db_url(project.id)
+                    .await
+                    .map_err(|e| format!("cannot read db_url: {}", e))?
+                    .ok_or_else(|| "project not found".to_string())?;
+                PgConnectOptions::from_str(&url).map_err(|e| e.to_string())?
+            }
+        };
+        let user = options.get_username().to_string();
+        // Postgres defaults the database to the user name
+        let database = options.get_database().unwrap_or(&user).to_string();
+
+        let options = options
+            .database(&self.config.maintenance_database)
+            .application_name("telemetrywatch-enforcer");
+        let connection = self.step(options.connect()).await?;
+        Ok((connection, database, user))
+    }
+
+    /// Grants or revokes LOGIN of `role`, unless it can also connect to a
+    /// database of the server other than `database`, the maintenance database
+    /// and templates: LOGIN applies to the whole server, so changing it would
+    /// block or unblock other projects too.
+    async fn set_login(
+        &self,
+        connection: &mut PgConnection,
+        role: &str,
+        database: &str,
+        login: bool,
+    ) -> Result<Option<String>, String> {
+        let shared: Vec<String> = self
+            .step(
+                sqlx::query_scalar(
+                    r#"
+                    SELECT datname::TEXT
+                    FROM pg_database
+                    WHERE NOT datistemplate
+                      AND datname NOT IN ($2, $3)
+                      AND has_database_privilege($1, datname, 'CONNECT')
+                    ORDER BY datname
+                    "#,
+                )
+                .bind(role)
+                .bind(database)
+                .bind(&self.config.maintenance_database)
+                .fetch_all(&mut *connection),
+            )
+            .await?;
+        if !shared.is_empty() {
+            return Err(format!(
+                "role can also connect to {}; its LOGIN is left unchanged",
+                shared.join(", ")
+            ));
+        }
+
+        let option = if login { "LOGIN" } else { "NOLOGIN" };
+        let sql = format!("ALTER ROLE {} {}", quote_ident(role), option);
+        self.execute(connection, &sql).await
+    }
+
+    async fn execute(
+        &self,
+        connection: &mut PgConnection,
+        sql: &str,
+    ) -> Result<Option<String>, String> {
+        self.step(sqlx::query(sql).execute(connection))
+            .await
+            .map(|_| None)
+    }
+
+    /// Runs one step within the configured timeout.
+    async fn step<T>(
+        &self,
+        future: impl Future<Output = Result<T, sqlx::Error>>,
+    ) -> Result<T, String> {
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        match tokio::time::timeout(timeout, future).await {
+            Ok(Ok(value)) => Ok(value),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("timed out after {}s", timeout.as_secs())),
+        }
+    }
+}
+
+#[async_trait]
+impl ProjectProvisioner for EnforcingProvisioner {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn provision(&self, project: &PlatformProject) -> anyhow::Result<Option<String>> {
+        self.inner.provision(project).await
+    }
+
+    async fn suspend(&self, project: &PlatformProject) -> anyhow::Result<()> {
+        self.inner.suspend(project).await
+    }
+
+    async fn resume(&self, project: &PlatformProject) -> anyhow::Result<()> {
+        self.inner.resume(project).await
+    }
+
+    async fn deprovision(&self, project: &PlatformProject) -> anyhow::Result<()> {
+        self.inner.deprovision(project).await
+    }
+
+    async fn transitioned(&self, project: &PlatformProject, action: ProvisionAction) {
+        self.inner.transitioned(project, action).await;
+        match action {
+            ProvisionAction::Suspend => self.enforce(project, EnforcementAction::Suspend).await,
+            ProvisionAction::Resume => self.enforce(project, EnforcementAction::Resume).await,
+            ProvisionAction::Provision | ProvisionAction::Deprovision => {}
+        }
+    }
+}
+
+impl Database {
+    /// Takes the enforcement lock of project `id` for the life of the returned
+    /// connection, and reads the project's status once it is held. The lock is
+    /// held by a connection of its own rather than one of the pool, as
+    /// enforcement waits on other servers while holding it.
+    async fn lock_enforcement(
+        &self,
+        id: i64,
+    ) -> anyhow::Result<(PgConnection, Option<ProjectStatus>)> {
+        let mut connection = PgConnection::connect_with(&self.pool.connect_options()).await?;
+        // IDs beyond the 32-bit range share locks, which only serializes more.
+        // The session lock is released when the connection closes
+        sqlx::query("SELECT pg_advisory_lock($1, ($2 % 2147483648)::INT)")
+            .bind(ENFORCEMENT_LOCK_CLASS)
+            .bind(id)
+            .execute(&mut connection)
+            .await?;
+        let status = sqlx::query_scalar("SELECT status FROM platform_projects WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&mut connection)
+            .await?;
+
+        Ok((connection, status))
+    }
+
+    async fn record_enforcement_steps(
+        &self,
+        project_id: i64,
+        action: EnforcementAction,
+        steps: &[StepResult],
+    ) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for step in steps {
+            let (succeeded, detail) = match &step.outcome {
+                Ok(detail) => (true, detail.as_deref()),
+                Err(e) => (false, Some(e.as_str())),
+            };
+            sqlx::query(
+                r#"
+                INSERT INTO platform_project_enforcement_steps
+                    (project_id, action, step, role, succeeded, detail)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                "#,
+            )
+            .bind(project_id)
+            .bind(action)
+            .bind(step.step)
+            .bind(&step.role)
+            .bind(succeeded)
+            .bind(detail)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn list_enforcement_steps(
+        &self,
+        project_id: i64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<EnforcementStep>> {
+        let steps = sqlx::query_as::<_, EnforcementStep>(
+            r#"
+            SELECT id, project_id, action, step, role, succeeded, detail, created_at
+            FROM platform_project_enforcement_steps
+            WHERE project_id = $1
+            ORDER BY id DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(project_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(steps)
+    }
+}
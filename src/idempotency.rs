@@ -0,0 +1,456 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::Response,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+use sqlx::types::Json;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::IdempotencyConfig;
+use crate::crypto::is_encrypted;
+use crate::db::Database;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const IDEMPOTENCY_REPLAYED_HEADER: &str = "idempotency-replayed";
+
+/// Response headers that describe the connection or the original exchange rather
+/// than the response, and are not replayed.
+const UNSTORED_HEADERS: [&str; 4] = ["content-length", "date", "transfer-encoding", "connection"];
+
+/// Response extension marking a response that carries a secret, such as a new
+/// API key. Without a keyring such responses are not stored, as they would be
+/// kept in plaintext until they expire.
+#[derive(Debug, Clone, Copy)]
+pub struct CarriesSecret;
+
+/// Outcome of claiming an idempotency key for a request.
+pub enum Claim {
+    /// The key is new (or expired): handle the request and store its response
+    /// under this claim token
+    Claimed(String),
+    /// Another request with the key is still being handled
+    InProgress,
+    /// The key was used for a request with a different method, path or body
+    Mismatch,
+    /// The key was used for the same request; replay its response
+    Completed(StoredResponse),
+}
+
+/// A response kept for replay.
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl StoredResponse {
+    pub fn capture(status: StatusCode, headers: &HeaderMap, body: &Bytes) -> Self {
+        let headers = headers
+            .iter()
+            .filter(|(name, _)| !UNSTORED_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.to_string(), value.to_string()))
+            })
+            .collect();
+        Self {
+            status: status.as_u16(),
+            headers,
+            body: body.to_vec(),
+        }
+    }
+
+    /// Rebuilds the response, marked with `Idempotency-Replayed: true`.
+    pub fn replay(self) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() =
+            StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let headers = response.headers_mut();
+        for (name, value) in self.headers {
+            if let (Ok(name), Ok(value)) =
+                (HeaderName::try_from(name), HeaderValue::try_from(value))
+            {
+                headers.append(name, value);
+            }
+        }
+        headers.insert(
+            IDEMPOTENCY_REPLAYED_HEADER,
+            HeaderValue::from_static("true"),
+        );
+        response
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct KeyRow {
+    request_hash: String,
+    status_code: Option<i16>,
+    response_headers: Option<Json<Vec<(String, String)>>>,
+    response_body: Option<Vec<u8>>,
+}
+
+/// Whether `key` is acceptable as an `Idempotency-Key`: 1-255 visible ASCII characters.
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= 255 && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Fingerprint of a request, to tell a retry from a different request reusing the key.
+pub fn request_hash(method: &Method, path_and_query: &str, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(method.as_str().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(path_and_query.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+/// Associated data binding an encrypted response body to its caller and key,
+/// so that it cannot be replayed to another caller.
+fn body_context(principal: &str, key: &str) -> String {
+    format!("idempotency_keys.response_body:{}:{}", principal, key)
+}
+
+/// Responses of mutating requests sent with an `Idempotency-Key`, kept in
+/// `idempotency_keys` for `ttl_secs` per caller and key. Response bodies can
+/// carry secrets, such as the signing secret of a new webhook subscription, so
+/// they are encrypted with the keyring if one is configured.
+pub struct IdempotencyStore {
+    db: Arc<Database>,
+    config: IdempotencyConfig,
+}
+
+impl IdempotencyStore {
+    pub fn new(db: Arc<Database>, config: IdempotencyConfig) -> Arc<Self> {
+        Arc::new(Self { db, config })
+    }
+
+    pub async fn claim(&self, principal: &str, key: &str, hash: &str) -> anyhow::Result<Claim> {
+        let claim = self
+            .db
+            .claim_idempotency_key(principal, key, hash, &self.config)
+            .await?;
+        match claim {
+            Claim::Completed(mut stored) => {
+                stored.body = self.open_body(principal, key, stored.body)?;
+                Ok(Claim::Completed(stored))
+            }
+            claim => Ok(claim),
+        }
+    }
+
+    /// Runs `request` while renewing the claim `token` on `key`, so that a retry
+    /// cannot take the key over from a request that is merely slow.
+    pub async fn hold<T>(
+        &self,
+        principal: &str,
+        key: &str,
+        token: &str,
+        request: impl Future<Output = T>,
+    ) -> T {
+        tokio::pin!(request);
+        let mut renew =
+            tokio::time::interval(Duration::from_secs(self.config.in_flight_timeout_secs / 3));
+        renew.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        renew.tick().await;
+        loop {
+            tokio::select! {
+                output = &mut request => return output,
+                _ = renew.tick() => {
+                    match self.db.renew_idempotency_key(principal, key, token, &self.config).await {
+                        Ok(true) => {}
+                        Ok(false) => tracing::warn!("Lost the claim on idempotency key {}", key),
+                        Err(e) => tracing::error!("Failed to renew idempotency key: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stores the response of the request holding the claim `token`. Returns
+    /// `false` if the claim lapsed and another request took the key over.
+    pub async fn complete(
+        &self,
+        principal: &str,
+        key: &str,
+        token: &str,
+        response: &StoredResponse,
+    ) -> anyhow::Result<bool> {
+        let sealed = StoredResponse {
+            status: response.status,
+            headers: response.headers.clone(),
+            body: self.seal_body(principal, key, &response.body)?,
+        };
+        self.db
+            .complete_idempotency_key(principal, key, token, &sealed)
+            .await
+    }
+
+    /// Forgets a key without storing a response, so that a retry is handled
+    /// again. Returns `false` if the claim `token` had already lapsed.
+    pub async fn release(&self, principal: &str, key: &str, token: &str) -> anyhow::Result<bool> {
+        self.db.release_idempotency_key(principal, key, token).await
+    }
+
+    /// Whether a response with `extensions` may be stored: those carrying
+    /// secrets only are if they can be encrypted.
+    pub fn can_store(&self, extensions: &axum::http::Extensions) -> bool {
+        self.db.keyring.is_some() || extensions.get::<CarriesSecret>().is_none()
+    }
+
+    fn seal_body(&self, principal: &str, key: &str, body: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match &self.db.keyring {
+            Some(keyring) => Ok(keyring
+                .encrypt(&STANDARD.encode(body), &body_context(principal, key))?
+                .into_bytes()),
+            None => Ok(body.to_vec()),
+        }
+    }
+
+    /// Reverses [`IdempotencyStore::seal_body`]. Bodies stored without a
+    /// keyring are returned as they are.
+    fn open_body(&self, principal: &str, key: &str, stored: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        match std::str::from_utf8(&stored) {
+            Ok(sealed) if is_encrypted(sealed) => {
+                let encoded = self
+                    .db
+                    .open_secret(sealed, &body_context(principal, key))?;
+                Ok(STANDARD.decode(encoded)?)
+            }
+            _ => Ok(stored),
+        }
+    }
+
+    /// Deletes expired keys every hour (or every TTL, if shorter). Meant to run
+    /// under [`crate::tasks::spawn_supervised`].
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
+        let period = Duration::from_secs(self.config.ttl_secs.min(3600));
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let pruned = self.db.prune_idempotency_keys().await?;
+            if pruned > 0 {
+                tracing::debug!("Pruned {} expired idempotency keys", pruned);
+            }
+        }
+    }
+}
+
+impl Database {
+    /// Claims `key` for a request, taking over keys that expired or whose
+    /// request's claim lapsed. Otherwise reports what the key was used for.
+    async fn claim_idempotency_key(
+        &self,
+        principal: &str,
+        key: &str,
+        hash: &str,
+        config: &IdempotencyConfig,
+    ) -> anyhow::Result<Claim> {
+        let token = uuid::Uuid::new_v4().to_string();
+        let claimed = sqlx::query(
+            r#"
+            INSERT INTO idempotency_keys
+                (principal, key, request_hash, expires_at, claim_token, locked_until)
+            VALUES ($1, $2, $3, NOW() + make_interval(secs => $4), $5,
+                    NOW() + make_interval(secs => $6))
+            ON CONFLICT (principal, key) DO UPDATE SET
+                request_hash = EXCLUDED.request_hash,
+                status_code = NULL,
+                response_headers = NULL,
+                response_body = NULL,
+                created_at = NOW(),
+                expires_at = EXCLUDED.expires_at,
+                claim_token = EXCLUDED.claim_token,
+                locked_until = EXCLUDED.locked_until
+            WHERE idempotency_keys.expires_at < NOW()
+               OR (idempotency_keys.status_code IS NULL
+                   AND COALESCE(idempotency_keys.locked_until,
+                                idempotency_keys.created_at + make_interval(secs => $6)) < NOW())
+            "#,
+        )
+        .bind(principal)
+        .bind(key)
+        .bind(hash)
+        .bind(config.ttl_secs as f64)
+        .bind(&token)
+        .bind(config.in_flight_timeout_secs as f64)
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if claimed {
+            return Ok(Claim::Claimed(token));
+        }
+
+        let row = sqlx::query_as::<_, KeyRow>(
+            r#"
+            SELECT request_hash, status_code, response_headers, response_body
+            FROM idempotency_keys
+            WHERE principal = $1 AND key = $2
+            "#,
+        )
+        .bind(principal)
+        .bind(key)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        // A key released between the two statements is treated as in progress;
+        // the client's next retry claims it
+        let claim = match row {
+            Some(row) if row.request_hash != hash => Claim::Mismatch,
+            Some(KeyRow {
+                status_code: Some(status),
+                response_headers,
+                response_body,
+                ..
+            }) => Claim::Completed(StoredResponse {
+                status: status as u16,
+                headers: response_headers.map(|h| h.0).unwrap_or_default(),
+                body: response_body.unwrap_or_default(),
+            }),
+            _ => Claim::InProgress,
+        };
+        Ok(claim)
+    }
+
+    /// Pushes back the lapse of the claim `token`. Returns `false` if the key
+    /// is no longer claimed with it.
+    async fn renew_idempotency_key(
+        &self,
+        principal: &str,
+        key: &str,
+        token: &str,
+        config: &IdempotencyConfig,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE idempotency_keys
+            SET locked_until = NOW() + make_interval(secs => $4)
+            WHERE principal = $1 AND key = $2 AND claim_token = $3 AND status_code IS NULL
+            "#,
+        )
+        .bind(principal)
+        .bind(key)
+        .bind(token)
+        .bind(config.in_flight_timeout_secs as f64)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        principal: &str,
+        key: &str,
+        token: &str,
+        response: &StoredResponse,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE idempotency_keys
+            SET status_code = $4, response_headers = $5, response_body = $6, locked_until = NULL
+            WHERE principal = $1 AND key = $2 AND claim_token = $3 AND status_code IS NULL
+            "#,
+        )
+        .bind(principal)
+        .bind(key)
+        .bind(token)
+        .bind(response.status as i16)
+        .bind(Json(&response.headers))
+        .bind(&response.body)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn release_idempotency_key(
+        &self,
+        principal: &str,
+        key: &str,
+        token: &str,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM idempotency_keys
+            WHERE principal = $1 AND key = $2 AND claim_token = $3 AND status_code IS NULL
+            "#,
+        )
+        .bind(principal)
+        .bind(key)
+        .bind(token)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn prune_idempotency_keys(&self) -> anyhow::Result<u64> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at < NOW()")
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_method_path_and_body() {
+        let hash = request_hash(&Method::POST, "/api/v1/platform/projects", b"{}");
+
+        assert_eq!(hash.len(), 64);
+        assert_eq!(
+            hash,
+            request_hash(&Method::POST, "/api/v1/platform/projects", b"{}")
+        );
+        assert_ne!(
+            hash,
+            request_hash(&Method::PUT, "/api/v1/platform/projects", b"{}")
+        );
+        assert_ne!(
+            hash,
+            request_hash(&Method::POST, "/api/v1/platform/projects?x=1", b"{}")
+        );
+        assert_ne!(
+            hash,
+            request_hash(&Method::POST, "/api/v1/platform/projects", b"{ }")
+        );
+    }
+
+    #[test]
+    fn validates_keys() {
+        assert!(is_valid_key("3f9c2a6e-retry"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("has space"));
+        assert!(!is_valid_key(&"k".repeat(256)));
+    }
+
+    #[test]
+    fn replays_captured_responses() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("application/json"));
+        headers.insert("content-length", HeaderValue::from_static("2"));
+        let stored = StoredResponse::capture(StatusCode::CREATED, &headers, &Bytes::from("{}"));
+        assert_eq!(
+            stored.headers,
+            vec![("content-type".to_string(), "application/json".to_string())]
+        );
+
+        let response = stored.replay();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[IDEMPOTENCY_REPLAYED_HEADER], "true");
+        assert_eq!(response.headers()["content-type"], "application/json");
+    }
+}
@@ -0,0 +1,300 @@
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::audit::NewAuditEntry;
+use crate::changes::{ChangeFeed, ChangeKind, ProjectChange};
+use crate::config::IdleConfig;
+use crate::db::Database;
+use crate::metrics::Metrics;
+use crate::organizations::{push_org_access, OrgAccess};
+use crate::platform::{LifecycleError, ProjectStatus};
+use crate::provisioner::ProjectProvisioner;
+
+/// Actor recorded in the event history and audit log of projects suspended for
+/// being idle.
+const IDLE_ACTOR: &str = "idle-detector";
+
+/// Reason recorded with the suspension.
+const IDLE_REASON: &str = "auto_idle";
+
+/// An active project that has been idle for longer than its plan allows, and
+/// that the idle detector suspends on its next round.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct IdleProject {
+    #[schema(example = 1)]
+    pub project_id: i64,
+    pub organization_id: i64,
+    #[schema(example = "my-dev-project")]
+    pub slug: String,
+    #[schema(example = "dev")]
+    pub plan: String,
+    /// The plan's `idle_suspend_after_hours`
+    #[schema(example = 24)]
+    pub idle_suspend_after_hours: i32,
+    /// Last time activity was seen, or the project was last resumed if that was later
+    pub idle_since: DateTime<Utc>,
+    #[schema(example = 30.5)]
+    pub idle_hours: f64,
+    /// When the project database's statistics were last collected
+    pub collected_at: DateTime<Utc>,
+}
+
+/// An active project with fresh statistics, on a plan that suspends idle projects.
+#[derive(Debug, Clone, FromRow)]
+struct IdleCandidate {
+    project_id: i64,
+    organization_id: i64,
+    slug: String,
+    plan: String,
+    idle_suspend_after_hours: i32,
+    last_active_at: DateTime<Utc>,
+    /// Last time the project became `active`, e.g. by being resumed
+    activated_at: Option<DateTime<Utc>>,
+    collected_at: DateTime<Utc>,
+}
+
+impl IdleCandidate {
+    /// The candidate as an [`IdleProject`] if at `now` it has been idle for
+    /// longer than its plan allows.
+    fn into_idle(self, now: DateTime<Utc>) -> Option<IdleProject> {
+        let idle_since = idle_since(self.last_active_at, self.activated_at);
+        let idle_for = now - idle_since;
+        if idle_for < ChronoDuration::hours(self.idle_suspend_after_hours.into()) {
+            return None;
+        }
+        Some(IdleProject {
+            project_id: self.project_id,
+            organization_id: self.organization_id,
+            slug: self.slug,
+            plan: self.plan,
+            idle_suspend_after_hours: self.idle_suspend_after_hours,
+            idle_since,
+            idle_hours: idle_for.num_milliseconds() as f64 / 3_600_000.0,
+            collected_at: self.collected_at,
+        })
+    }
+}
+
+/// Idle time counts from the last activity or the last resume, whichever is
+/// later, so that a resumed project gets a full idle period before it is
+/// suspended again.
+fn idle_since(last_active_at: DateTime<Utc>, activated_at: Option<DateTime<Utc>>) -> DateTime<Utc> {
+    activated_at.map_or(last_active_at, |resumed| resumed.max(last_active_at))
+}
+
+/// Suspends active projects that have been idle for longer than their plan's
+/// `idle_suspend_after_hours`, through the normal lifecycle path with the
+/// reason `auto_idle`.
+///
+/// A project is idle while the tenant stats collector sees no active client
+/// connections and no reads or writes on its tables. Projects whose statistics
+/// are stale or whose latest health probe failed are left alone: no data is
+/// not the same as no activity.
+pub struct IdleDetector {
+    db: Arc<Database>,
+    metrics: Arc<Metrics>,
+    changes: Arc<ChangeFeed>,
+    provisioner: Arc<dyn ProjectProvisioner>,
+    config: IdleConfig,
+    /// Statistics older than this are stale
+    stats_max_age: Duration,
+}
+
+impl IdleDetector {
+    pub fn new(
+        db: Arc<Database>,
+        metrics: Arc<Metrics>,
+        changes: Arc<ChangeFeed>,
+        provisioner: Arc<dyn ProjectProvisioner>,
+        config: IdleConfig,
+        stats_interval: Duration,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            db,
+            metrics,
+            changes,
+            provisioner,
+            config,
+            stats_max_age: stats_interval * 3,
+        })
+    }
+
+    /// Checks for idle projects every `interval_secs`. Meant to run under
+    /// [`crate::tasks::spawn_supervised`].
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.suspend_idle().await?;
+        }
+    }
+
+    /// Projects visible with `access` that would be suspended on the next round.
+    pub async fn report(&self, access: &OrgAccess) -> anyhow::Result<Vec<IdleProject>> {
+        self.db.list_idle_projects(self.stats_max_age, access).await
+    }
+
+    async fn suspend_idle(&self) -> anyhow::Result<()> {
+        let idle = self.report(&OrgAccess::All).await?;
+
+        for project in idle {
+            let before = self.db.get_platform_project(project.project_id).await?;
+            let result = self
+                .db
+                .update_platform_project_status(
+                    self.provisioner.as_ref(),
+                    project.project_id,
+                    ProjectStatus::Suspended,
+                    IDLE_ACTOR,
+                    Some(IDLE_REASON),
+                    None,
+                )
+                .await;
+            let entry = NewAuditEntry::background(
+                IDLE_ACTOR,
+                format!("idle-{}", project.project_id),
+                "project.suspend",
+                "platform_project",
+                Some(project.project_id.to_string()),
+            )
+            .before(before.as_ref())
+            .after(result.as_ref().ok())
+            .status(result.as_ref().map_or_else(LifecycleError::status_code, |_| 200))
+            .error(result.as_ref().err());
+            self.db.record_audit(entry, &self.metrics).await;
+            match result {
+                Ok(suspended) => {
+                    tracing::info!(
+                        "Suspended project {} after {:.1} idle hours",
+                        project.slug,
+                        project.idle_hours
+                    );
+                    self.metrics
+                        .platform_projects_auto_suspended_total
+                        .with_label_values(&[&project.plan])
+                        .inc();
+                    self.changes.publish(
+                        ProjectChange::new(ChangeKind::StatusChanged, &suspended, IDLE_ACTOR)
+                            .previous_status(ProjectStatus::Active),
+                    );
+                }
+                // Changed meanwhile, e.g. suspended by another replica
+                Err(LifecycleError::InvalidTransition { .. } | LifecycleError::NotFound) => {}
+                Err(e) => {
+                    tracing::error!("Failed to suspend idle project {}: {}", project.slug, e)
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Active projects visible with `access` that have been idle for longer than
+    /// their plan allows, by statistics collected within `stats_max_age`, longest
+    /// idle first.
+    pub async fn list_idle_projects(
+        &self,
+        stats_max_age: Duration,
+        access: &OrgAccess,
+    ) -> anyhow::Result<Vec<IdleProject>> {
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(
+            r#"
+            SELECT *
+            FROM (
+                SELECT p.id AS project_id, p.organization_id, p.slug, p.plan,
+                       pl.idle_suspend_after_hours, u.last_active_at, a.activated_at,
+                       u.collected_at
+                FROM platform_projects p
+                JOIN plans pl ON pl.name = p.plan
+                JOIN platform_project_usage u ON u.project_id = p.id
+                LEFT JOIN LATERAL (
+                    SELECT MAX(e.created_at) AS activated_at
+                    FROM platform_project_events e
+                    WHERE e.project_id = p.id AND e.to_status = 'active'
+                ) a ON TRUE
+                LEFT JOIN LATERAL (
+                    SELECT pr.up
+                    FROM platform_project_probes pr
+                    WHERE pr.project_id = p.id
+                    ORDER BY pr.probed_at DESC
+                    LIMIT 1
+                ) probe ON TRUE
+                WHERE p.status = 'active'
+                  AND pl.idle_suspend_after_hours IS NOT NULL
+                  AND u.last_active_at IS NOT NULL
+                  AND probe.up IS NOT FALSE
+                  AND u.collected_at > NOW() - make_interval(secs => "#,
+        );
+        builder.push_bind(stats_max_age.as_secs_f64()).push(
+            r#")
+            ) candidates
+            WHERE TRUE"#,
+        );
+        push_org_access(&mut builder, access);
+
+        let candidates = builder
+            .build_query_as::<IdleCandidate>()
+            .fetch_all(&self.pool)
+            .await?;
+        let now = Utc::now();
+        let mut idle: Vec<IdleProject> = candidates
+            .into_iter()
+            .filter_map(|candidate| candidate.into_idle(now))
+            .collect();
+        idle.sort_by_key(|p| (p.idle_since, p.project_id));
+
+        Ok(idle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        format!("2024-05-01T{}:00Z", time).parse().unwrap()
+    }
+
+    fn candidate(last_active_at: &str, activated_at: Option<&str>) -> IdleCandidate {
+        IdleCandidate {
+            project_id: 1,
+            organization_id: 1,
+            slug: "acme".to_string(),
+            plan: "dev".to_string(),
+            idle_suspend_after_hours: 2,
+            last_active_at: at(last_active_at),
+            activated_at: activated_at.map(at),
+            collected_at: at("12:00"),
+        }
+    }
+
+    #[test]
+    fn counts_idle_time_from_the_later_of_activity_and_resume() {
+        assert_eq!(idle_since(at("08:00"), None), at("08:00"));
+        assert_eq!(idle_since(at("08:00"), Some(at("06:00"))), at("08:00"));
+        assert_eq!(idle_since(at("08:00"), Some(at("09:30"))), at("09:30"));
+    }
+
+    #[test]
+    fn reports_projects_idle_for_longer_than_their_plan_allows() {
+        let idle = candidate("08:00", Some("06:00"))
+            .into_idle(at("10:30"))
+            .unwrap();
+        assert_eq!(idle.idle_since, at("08:00"));
+        assert_eq!(idle.idle_hours, 2.5);
+        assert!(candidate("08:00", None).into_idle(at("10:00")).is_some());
+
+        // A recent resume restarts the idle period
+        assert!(candidate("08:00", Some("09:30"))
+            .into_idle(at("10:30"))
+            .is_none());
+        assert!(candidate("08:00", None).into_idle(at("09:59")).is_none());
+    }
+}
@@ -0,0 +1,649 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::audit::NewAuditEntry;
+use crate::changes::{ChangeFeed, ChangeKind, ProjectChange};
+use crate::config::JobConfig;
+use crate::db::Database;
+use crate::metrics::Metrics;
+use crate::organizations::{push_org_access, OrgAccess};
+use crate::platform::{string_enum, LifecycleError, PlatformProject, ProjectStatus};
+use crate::provisioner::ProjectProvisioner;
+
+/// Longest wait between attempts of a job.
+const MAX_BACKOFF_SECS: u64 = 3600;
+
+/// Seconds between rounds of counting, expiring and pruning jobs.
+const MAINTENANCE_INTERVAL_SECS: u64 = 60;
+
+const JOB_COLUMNS: &str = "id, kind, status, project_id, payload, attempts, max_attempts, \
+                           run_at, last_error, result, created_by, created_at, started_at, \
+                           finished_at";
+
+string_enum! {
+    /// What a job does
+    pub enum JobKind {
+        Provision => "project.provision",
+        Suspend => "project.suspend",
+        Resume => "project.resume",
+        Delete => "project.delete",
+    }
+}
+
+string_enum! {
+    /// State of a job: `queued` for its first attempt or a retry, `failed` if it
+    /// cannot be carried out (e.g. the project's status no longer allows it), and
+    /// `dead` once `max_attempts` attempts have failed
+    pub enum JobStatus {
+        Queued => "queued",
+        Running => "running",
+        Succeeded => "succeeded",
+        Failed => "failed",
+        Dead => "dead",
+    }
+}
+
+impl JobKind {
+    /// The statuses a lifecycle job moves a project in status `from` through.
+    /// Starting from where the project is, a retry picks up after a partly
+    /// completed attempt.
+    pub fn path(self, from: ProjectStatus) -> Vec<ProjectStatus> {
+        match (self, from) {
+            (JobKind::Provision, ProjectStatus::Failed) => {
+                vec![ProjectStatus::Provisioning, ProjectStatus::Active]
+            }
+            (JobKind::Provision | JobKind::Resume, _) => vec![ProjectStatus::Active],
+            (JobKind::Suspend, _) => vec![ProjectStatus::Suspended],
+            (JobKind::Delete, ProjectStatus::Deleting) => vec![ProjectStatus::Deleted],
+            (JobKind::Delete, _) => vec![ProjectStatus::Deleting, ProjectStatus::Deleted],
+        }
+    }
+}
+
+/// An operation run in the background by the job workers.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct Job {
+    #[schema(example = 1)]
+    pub id: i64,
+    pub kind: JobKind,
+    pub status: JobStatus,
+    /// Project the job works on
+    #[schema(example = 1)]
+    pub project_id: Option<i64>,
+    /// Parameters of the job, such as the `reason` of a lifecycle transition
+    #[schema(value_type = Object)]
+    pub payload: Json<Value>,
+    /// Attempts made so far
+    pub attempts: i32,
+    pub max_attempts: i32,
+    /// When the job is due to run, while queued
+    pub run_at: DateTime<Utc>,
+    /// Why the last attempt failed
+    pub last_error: Option<String>,
+    /// Outcome of a succeeded job; the project after the change for lifecycle jobs
+    #[schema(value_type = Option<Object>)]
+    pub result: Option<Json<Value>>,
+    /// Actor that queued the job, recorded with the changes it makes
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    /// Start of the last attempt
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    /// Where the job can be polled.
+    pub fn location(&self) -> String {
+        format!("/api/v1/jobs/{}", self.id)
+    }
+
+    fn reason(&self) -> Option<&str> {
+        self.payload.get("reason").and_then(Value::as_str)
+    }
+}
+
+/// Query parameters for listing jobs.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobQuery {
+    /// Only jobs in this state, e.g. `dead` for the dead-letter queue
+    pub status: Option<JobStatus>,
+    /// Only jobs of this project
+    pub project_id: Option<i64>,
+    /// Maximum number of jobs to return, newest first (default 50, max 1000)
+    pub limit: Option<i64>,
+}
+
+impl JobQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50).clamp(1, 1000)
+    }
+}
+
+/// Wait before the attempt following attempt number `attempts`: `base_secs`
+/// doubled for every earlier attempt, up to an hour.
+fn backoff(base_secs: u64, attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+    Duration::from_secs(
+        base_secs
+            .saturating_mul(1 << exponent)
+            .min(MAX_BACKOFF_SECS),
+    )
+}
+
+/// How an attempt ended.
+enum Attempt {
+    Succeeded(PlatformProject),
+    /// Worth another attempt, e.g. the provisioner was unreachable
+    Retryable(String),
+    Failed(String),
+}
+
+/// Postgres-backed queue of long-running operations, such as lifecycle
+/// transitions whose provisioner calls take longer than a request should.
+///
+/// Workers on every instance claim due jobs with `FOR UPDATE SKIP LOCKED`.
+/// A project's jobs run one at a time, in the order they were queued. Failed
+/// attempts are retried with exponential backoff until `max_attempts`, after
+/// which the job is `dead` and can be queued again by hand. Workers renew the
+/// lease of a running job, so a job whose worker disappeared is picked up again
+/// once its lease expires. Transitions made by jobs are audited with the actor
+/// that queued them.
+pub struct JobQueue {
+    db: Arc<Database>,
+    metrics: Arc<Metrics>,
+    changes: Arc<ChangeFeed>,
+    provisioner: Arc<dyn ProjectProvisioner>,
+    config: JobConfig,
+    /// Wakes an idle worker of this instance when a job is queued
+    queued: Notify,
+}
+
+impl JobQueue {
+    pub fn new(
+        db: Arc<Database>,
+        metrics: Arc<Metrics>,
+        changes: Arc<ChangeFeed>,
+        provisioner: Arc<dyn ProjectProvisioner>,
+        config: JobConfig,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            db,
+            metrics,
+            changes,
+            provisioner,
+            config,
+            queued: Notify::new(),
+        })
+    }
+
+    /// Queues a job on `project_id`, to run as soon as a worker is free.
+    pub async fn enqueue(
+        &self,
+        kind: JobKind,
+        project_id: i64,
+        payload: Value,
+        actor: &str,
+    ) -> anyhow::Result<Job> {
+        let job = self
+            .db
+            .insert_job(kind, project_id, payload, self.config.max_attempts, actor)
+            .await?;
+        self.queued.notify_one();
+        Ok(job)
+    }
+
+    /// Queues a failed or dead job again with a fresh set of attempts. Returns
+    /// `None` if the job does not exist or has not failed.
+    pub async fn retry(&self, id: i64) -> anyhow::Result<Option<Job>> {
+        let job = self.db.retry_job(id).await?;
+        if job.is_some() {
+            self.queued.notify_one();
+        }
+        Ok(job)
+    }
+
+    /// Runs jobs one after another, waiting up to `poll_interval_secs` for new
+    /// ones when the queue is empty. Several workers may run at once. Meant to
+    /// run under [`crate::tasks::spawn_supervised`].
+    pub async fn work(self: Arc<Self>) -> anyhow::Result<()> {
+        let poll = Duration::from_secs(self.config.poll_interval_secs);
+        let lease = Duration::from_secs(self.config.lease_secs);
+        loop {
+            match self.db.claim_job(lease).await? {
+                Some(job) => self.process(job).await,
+                None => {
+                    tokio::select! {
+                        _ = self.queued.notified() => {}
+                        _ = tokio::time::sleep(poll) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// Keeps the queue gauge current, gives up on jobs whose worker disappeared
+    /// during their last attempt, and prunes finished jobs after
+    /// `retention_days`. Meant to run under [`crate::tasks::spawn_supervised`].
+    pub async fn maintain(self: Arc<Self>) -> anyhow::Result<()> {
+        let mut interval = tokio::time::interval(Duration::from_secs(MAINTENANCE_INTERVAL_SECS));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let expired = self.db.expire_abandoned_jobs().await?;
+            if expired > 0 {
+                tracing::warn!("{} jobs lost their worker on their last attempt", expired);
+            }
+            self.db.prune_jobs(self.config.retention_days).await?;
+            let queued = self.db.count_queued_jobs().await?;
+            self.metrics.jobs_queued.set(queued as f64);
+        }
+    }
+
+    async fn process(&self, job: Job) {
+        let attempt = self.hold(&job, self.execute(&job)).await;
+        let exhausted = job.attempts >= job.max_attempts;
+        let (status, outcome) = match &attempt {
+            Attempt::Succeeded(_) => (JobStatus::Succeeded, "succeeded"),
+            Attempt::Retryable(_) if exhausted => (JobStatus::Dead, "dead"),
+            Attempt::Retryable(_) => (JobStatus::Queued, "retried"),
+            Attempt::Failed(_) => (JobStatus::Failed, "failed"),
+        };
+        self.metrics
+            .jobs_total
+            .with_label_values(&[job.kind.as_str(), outcome])
+            .inc();
+
+        let (result, error) = match attempt {
+            Attempt::Succeeded(project) => (serde_json::to_value(project).ok(), None),
+            Attempt::Retryable(e) | Attempt::Failed(e) => (None, Some(e)),
+        };
+        if let Some(e) = &error {
+            tracing::warn!(
+                "Job {} ({}) attempt {} of {} failed: {}",
+                job.id,
+                job.kind,
+                job.attempts,
+                job.max_attempts,
+                e
+            );
+        }
+        let retry_in = backoff(self.config.retry_base_secs, job.attempts);
+        match self
+            .db
+            .finish_job_attempt(&job, status, result, error.as_deref(), retry_in)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => tracing::warn!(
+                "Job {} was taken over after its lease expired; attempt {} is not recorded",
+                job.id,
+                job.attempts
+            ),
+            Err(e) => tracing::error!("Failed to record attempt of job {}: {}", job.id, e),
+        }
+    }
+
+    /// Runs `attempt` of `job` while renewing the job's lease, so that a slow
+    /// attempt is not taken over and run a second time by another worker.
+    async fn hold(&self, job: &Job, attempt: impl Future<Output = Attempt>) -> Attempt {
+        tokio::pin!(attempt);
+        let lease = Duration::from_secs(self.config.lease_secs);
+        let mut renew = tokio::time::interval((lease / 3).max(Duration::from_secs(1)));
+        renew.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        renew.tick().await;
+        loop {
+            tokio::select! {
+                outcome = &mut attempt => return outcome,
+                _ = renew.tick() => {
+                    match self.db.renew_job_lease(job, lease).await {
+                        Ok(true) => {}
+                        Ok(false) => tracing::warn!("Lost the lease on job {}", job.id),
+                        Err(e) => tracing::error!("Failed to renew lease of job {}: {}", job.id, e),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn execute(&self, job: &Job) -> Attempt {
+        let Some(project_id) = job.project_id else {
+            return Attempt::Failed("job has no project".to_string());
+        };
+        let project = match self.db.get_platform_project(project_id).await {
+            Ok(Some(project)) => project,
+            Ok(None) => return Attempt::Failed(LifecycleError::NotFound.to_string()),
+            Err(e) => return Attempt::Retryable(e.to_string()),
+        };
+
+        let path = job.kind.path(project.status);
+        let mut current = project;
+        for status in path {
+            let result = self
+                .db
+                .update_platform_project_status(
+                    self.provisioner.as_ref(),
+                    current.id,
+                    status,
+                    &job.created_by,
+                    job.reason(),
+                    None,
+                )
+                .await;
+            let entry = NewAuditEntry::background(
+                &job.created_by,
+                format!("job-{}", job.id),
+                job.kind.as_str(),
+                "platform_project",
+                Some(current.id.to_string()),
+            )
+            .before(Some(&current))
+            .after(result.as_ref().ok())
+            .status(result.as_ref().map_or_else(LifecycleError::status_code, |_| 200))
+            .error(result.as_ref().err());
+            self.db.record_audit(entry, &self.metrics).await;
+            match result {
+                Ok(project) => {
+                    self.changes.publish(
+                        ProjectChange::new(ChangeKind::StatusChanged, &project, &job.created_by)
+                            .previous_status(current.status),
+                    );
+                    current = project;
+                }
+                Err(e @ (LifecycleError::Provisioner(_) | LifecycleError::Database(_))) => {
+                    return Attempt::Retryable(e.to_string())
+                }
+                Err(e) => return Attempt::Failed(e.to_string()),
+            }
+        }
+
+        Attempt::Succeeded(current)
+    }
+}
+
+impl Database {
+    async fn insert_job(
+        &self,
+        kind: JobKind,
+        project_id: i64,
+        payload: Value,
+        max_attempts: i32,
+        created_by: &str,
+    ) -> anyhow::Result<Job> {
+        let job = sqlx::query_as::<_, Job>(&format!(
+            r#"
+            INSERT INTO jobs (kind, project_id, payload, max_attempts, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {JOB_COLUMNS}
+            "#,
+        ))
+        .bind(kind)
+        .bind(project_id)
+        .bind(Json(payload))
+        .bind(max_attempts)
+        .bind(created_by)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    pub async fn get_job(&self, id: i64) -> anyhow::Result<Option<Job>> {
+        let job =
+            sqlx::query_as::<_, Job>(&format!("SELECT {JOB_COLUMNS} FROM jobs WHERE id = $1"))
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+
+        Ok(job)
+    }
+
+    /// Jobs on projects visible with `access`, newest first.
+    pub async fn list_jobs(
+        &self,
+        query: &JobQuery,
+        access: &OrgAccess,
+    ) -> anyhow::Result<Vec<Job>> {
+        let mut builder: QueryBuilder<Postgres> =
+            QueryBuilder::new(format!("SELECT {JOB_COLUMNS} FROM jobs WHERE TRUE"));
+        if let Some(status) = query.status {
+            builder.push(" AND status = ").push_bind(status);
+        }
+        if let Some(project_id) = query.project_id {
+            builder.push(" AND project_id = ").push_bind(project_id);
+        }
+        if !matches!(access, OrgAccess::All) {
+            builder.push(" AND project_id IN (SELECT id FROM platform_projects WHERE TRUE");
+            push_org_access(&mut builder, access);
+            builder.push(")");
+        }
+        builder
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(query.limit());
+
+        let jobs = builder
+            .build_query_as::<Job>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(jobs)
+    }
+
+    async fn retry_job(&self, id: i64) -> anyhow::Result<Option<Job>> {
+        let job = sqlx::query_as::<_, Job>(&format!(
+            r#"
+            UPDATE jobs
+            SET status = 'queued', attempts = 0, run_at = NOW(), finished_at = NULL
+            WHERE id = $1 AND status IN ('failed', 'dead')
+            RETURNING {JOB_COLUMNS}
+            "#,
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Takes the next due job, counting an attempt and leasing it for `lease`.
+    /// Jobs of a project wait until the project's earlier jobs have finished, so
+    /// that they never run concurrently or out of order. Running jobs whose lease
+    /// expired with attempts left are taken over.
+    async fn claim_job(&self, lease: Duration) -> anyhow::Result<Option<Job>> {
+        let job = sqlx::query_as::<_, Job>(&format!(
+            r#"
+            UPDATE jobs
+            SET status = 'running',
+                attempts = attempts + 1,
+                started_at = NOW(),
+                locked_until = NOW() + make_interval(secs => $1)
+            WHERE id = (
+                SELECT j.id
+                FROM jobs j
+                WHERE ((j.status = 'queued' AND j.run_at <= NOW())
+                       OR (j.status = 'running' AND j.locked_until <= NOW()
+                           AND j.attempts < j.max_attempts))
+                  AND NOT EXISTS (
+                      SELECT 1 FROM jobs e
+                      WHERE e.project_id = j.project_id AND e.id < j.id
+                        AND e.status IN ('queued', 'running')
+                  )
+                ORDER BY j.run_at, j.id
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING {JOB_COLUMNS}
+            "#,
+        ))
+        .bind(lease.as_secs_f64())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Extends the lease of the attempt `job` was claimed for by `lease` from
+    /// now. Returns `false` if the job has been claimed again since.
+    async fn renew_job_lease(&self, job: &Job, lease: Duration) -> anyhow::Result<bool> {
+        let renewed = sqlx::query(
+            r#"
+            UPDATE jobs
+            SET locked_until = NOW() + make_interval(secs => $2)
+            WHERE id = $1 AND status = 'running' AND attempts = $3
+              AND started_at IS NOT DISTINCT FROM $4
+            "#,
+        )
+        .bind(job.id)
+        .bind(lease.as_secs_f64())
+        .bind(job.attempts)
+        .bind(job.started_at)
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+
+        Ok(renewed)
+    }
+
+    /// Records the outcome of the attempt `job` was claimed for. Returns `false`
+    /// without recording anything if the job has been claimed again since,
+    /// because this attempt's lease expired.
+    async fn finish_job_attempt(
+        &self,
+        job: &Job,
+        status: JobStatus,
+        result: Option<Value>,
+        error: Option<&str>,
+        retry_in: Duration,
+    ) -> anyhow::Result<bool> {
+        let finished = sqlx::query(
+            r#"
+            UPDATE jobs
+            SET status = $2,
+                result = $3,
+                last_error = COALESCE($4, last_error),
+                locked_until = NULL,
+                run_at = CASE WHEN $2 = 'queued'
+                              THEN NOW() + make_interval(secs => $5)
+                              ELSE run_at END,
+                finished_at = CASE WHEN $2 <> 'queued' THEN NOW() END
+            WHERE id = $1 AND status = 'running' AND attempts = $6
+              AND started_at IS NOT DISTINCT FROM $7
+            "#,
+        )
+        .bind(job.id)
+        .bind(status)
+        .bind(result.map(Json))
+        .bind(error)
+        .bind(retry_in.as_secs_f64())
+        .bind(job.attempts)
+        .bind(job.started_at)
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+
+        Ok(finished)
+    }
+
+    /// Marks running jobs as dead whose lease expired on their last attempt.
+    /// Returns how many there were.
+    async fn expire_abandoned_jobs(&self) -> anyhow::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE jobs
+            SET status = 'dead',
+                last_error = 'worker stopped during the last attempt',
+                locked_until = NULL,
+                finished_at = NOW()
+            WHERE status = 'running' AND locked_until <= NOW() AND attempts >= max_attempts
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn count_queued_jobs(&self) -> anyhow::Result<i64> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM jobs WHERE status = 'queued'")
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(count)
+    }
+
+    async fn prune_jobs(&self, retention_days: i64) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM jobs
+            WHERE status IN ('succeeded', 'failed')
+              AND finished_at < NOW() - make_interval(days => $1)
+            "#,
+        )
+        .bind(retention_days as i32)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plans_paths_from_the_current_status() {
+        use ProjectStatus::*;
+
+        assert_eq!(JobKind::Provision.path(Provisioning), vec![Active]);
+        assert_eq!(JobKind::Provision.path(Failed), vec![Provisioning, Active]);
+        assert_eq!(JobKind::Suspend.path(Active), vec![Suspended]);
+        assert_eq!(JobKind::Resume.path(Suspended), vec![Active]);
+        assert_eq!(JobKind::Delete.path(Active), vec![Deleting, Deleted]);
+        assert_eq!(JobKind::Delete.path(Failed), vec![Deleting, Deleted]);
+        // A retry after the project reached `deleting` only finishes the deletion
+        assert_eq!(JobKind::Delete.path(Deleting), vec![Deleted]);
+    }
+
+    #[test]
+    fn paths_are_valid_transitions() {
+        use ProjectStatus::*;
+
+        let starts = [
+            (JobKind::Provision, Provisioning),
+            (JobKind::Provision, Failed),
+            (JobKind::Suspend, Active),
+            (JobKind::Resume, Suspended),
+            (JobKind::Delete, Active),
+            (JobKind::Delete, Suspended),
+            (JobKind::Delete, Failed),
+            (JobKind::Delete, Deleting),
+        ];
+        for (kind, from) in starts {
+            let mut status = from;
+            for next in kind.path(from) {
+                assert!(status.can_transition_to(next), "{} from {}", kind, from);
+                status = next;
+            }
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_an_hour() {
+        assert_eq!(backoff(5, 1), Duration::from_secs(5));
+        assert_eq!(backoff(5, 2), Duration::from_secs(10));
+        assert_eq!(backoff(5, 4), Duration::from_secs(40));
+        assert_eq!(backoff(5, 0), Duration::from_secs(5));
+        assert_eq!(backoff(5, 100), Duration::from_secs(MAX_BACKOFF_SECS));
+    }
+}
@@ -0,0 +1,342 @@
+use serde_json::{Map, Value};
+use sqlx::types::Json;
+use sqlx::{Postgres, QueryBuilder};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::validation::ValidationErrors;
+
+/// Key/value labels attached to a project, e.g. `team=payments`.
+pub type Labels = BTreeMap<String, String>;
+
+/// Most labels a project may carry.
+pub const MAX_LABELS: usize = 64;
+
+/// Whether `key` is a valid label key: an optional DNS subdomain prefix of at most
+/// 253 characters followed by `/`, and a name as described in [`is_valid_label_name`].
+pub fn is_valid_label_key(key: &str) -> bool {
+    match key.split_once('/') {
+        Some((prefix, name)) => {
+            !prefix.is_empty()
+                && prefix.len() <= 253
+                && prefix.split('.').all(|part| {
+                    !part.is_empty()
+                        && part
+                            .chars()
+                            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+                        && !part.starts_with('-')
+                        && !part.ends_with('-')
+                })
+                && is_valid_label_name(name)
+        }
+        None => is_valid_label_name(key),
+    }
+}
+
+/// Whether `name` is 1-63 letters, digits, `-`, `_` or `.`, starting and ending
+/// with a letter or digit.
+fn is_valid_label_name(name: &str) -> bool {
+    !name.is_empty() && is_valid_label_value(name)
+}
+
+/// Whether `value` is a valid label value: empty, or 1-63 letters, digits, `-`,
+/// `_` or `.`, starting and ending with a letter or digit.
+pub fn is_valid_label_value(value: &str) -> bool {
+    value.is_empty()
+        || (value.len() <= 63
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            && value.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && value.ends_with(|c: char| c.is_ascii_alphanumeric()))
+}
+
+/// Records an error on `field` for every invalid key or value in `labels`, and
+/// if there are more than [`MAX_LABELS`].
+pub fn validate_labels(labels: &Labels, field: &str, errors: &mut ValidationErrors) {
+    if labels.len() > MAX_LABELS {
+        errors.add(field, format!("must have at most {} labels", MAX_LABELS));
+    }
+    for (key, value) in labels {
+        if !is_valid_label_key(key) {
+            errors.add(
+                field,
+                format!(
+                    "'{}' is not a valid key: an optional DNS prefix and '/', then 1-63 \
+                     letters, digits, '-', '_' or '.', starting and ending with a letter or digit",
+                    key
+                ),
+            );
+        }
+        if !is_valid_label_value(value) {
+            errors.add(
+                field,
+                format!(
+                    "'{}' is not a valid value for '{}': at most 63 letters, digits, '-', '_' \
+                     or '.', starting and ending with a letter or digit",
+                    value, key
+                ),
+            );
+        }
+    }
+}
+
+/// Name of the Prometheus label a project label is promoted to: `label_` followed
+/// by the key with every character other than a letter, digit or `_` replaced by `_`.
+pub fn metric_label_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("label_{}", name)
+}
+
+/// One condition of a [`LabelSelector`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    /// `key=value` or `key==value`
+    Equals(String, String),
+    /// `key!=value`; also matches projects without the label
+    NotEquals(String, String),
+    /// `key in (a,b)`
+    In(String, Vec<String>),
+    /// `key notin (a,b)`; also matches projects without the label
+    NotIn(String, Vec<String>),
+    /// `key`
+    Exists(String),
+    /// `!key`
+    NotExists(String),
+}
+
+/// A Kubernetes-style label selector such as `team=payments,tier!=dev`: a
+/// comma-separated list of requirements that must all hold.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSelector(pub Vec<Requirement>);
+
+impl FromStr for LabelSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut requirements = Vec::new();
+        for term in split_terms(s)? {
+            if !term.is_empty() {
+                requirements.push(parse_requirement(term)?);
+            }
+        }
+        Ok(LabelSelector(requirements))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for LabelSelector {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse()
+            .map_err(|e| serde::de::Error::custom(format!("invalid label selector: {}", e)))
+    }
+}
+
+/// Splits a selector on the commas that are not inside the parentheses of a set.
+fn split_terms(s: &str) -> Result<Vec<&str>, String> {
+    let mut terms = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' if depth == 0 => depth = 1,
+            ')' if depth == 1 => depth = 0,
+            '(' | ')' => return Err("unbalanced parentheses".to_string()),
+            ',' if depth == 0 => {
+                terms.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err("unbalanced parentheses".to_string());
+    }
+    terms.push(s[start..].trim());
+    Ok(terms)
+}
+
+fn parse_requirement(term: &str) -> Result<Requirement, String> {
+    if let Some(key) = term.strip_prefix('!') {
+        return Ok(Requirement::NotExists(parse_key(key)?));
+    }
+
+    if let Some((head, values)) = term.strip_suffix(')').and_then(|t| t.split_once('(')) {
+        let mut words = head.split_whitespace();
+        let (Some(key), Some(operator), None) = (words.next(), words.next(), words.next()) else {
+            return Err(format!("'{}' must look like 'key in (a,b)'", term));
+        };
+        let key = parse_key(key)?;
+        let values = values
+            .split(',')
+            .map(|value| parse_value(value.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        return match operator {
+            "in" => Ok(Requirement::In(key, values)),
+            "notin" => Ok(Requirement::NotIn(key, values)),
+            _ => Err(format!(
+                "unknown operator '{}'; use 'in' or 'notin'",
+                operator
+            )),
+        };
+    }
+
+    if let Some((key, value)) = term.split_once("!=") {
+        return Ok(Requirement::NotEquals(
+            parse_key(key)?,
+            parse_value(value.trim())?,
+        ));
+    }
+    if let Some((key, value)) = term.split_once("==").or_else(|| term.split_once('=')) {
+        return Ok(Requirement::Equals(
+            parse_key(key)?,
+            parse_value(value.trim())?,
+        ));
+    }
+    Ok(Requirement::Exists(parse_key(term)?))
+}
+
+fn parse_key(key: &str) -> Result<String, String> {
+    let key = key.trim();
+    if is_valid_label_key(key) {
+        Ok(key.to_string())
+    } else {
+        Err(format!("'{}' is not a valid label key", key))
+    }
+}
+
+fn parse_value(value: &str) -> Result<String, String> {
+    if is_valid_label_value(value) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("'{}' is not a valid label value", value))
+    }
+}
+
+/// JSON object `{key: value}`, for containment checks that can use the GIN index.
+fn label_object(key: &str, value: &str) -> Json<Value> {
+    let mut object = Map::new();
+    object.insert(key.to_string(), Value::String(value.to_string()));
+    Json(Value::Object(object))
+}
+
+/// Appends ` AND ...` conditions on the `labels` column for every requirement of
+/// `selector` to a query that already has a WHERE clause.
+pub fn push_label_selector<'a>(
+    builder: &mut QueryBuilder<'a, Postgres>,
+    selector: &'a LabelSelector,
+) {
+    for requirement in &selector.0 {
+        match requirement {
+            Requirement::Equals(key, value) => {
+                builder
+                    .push(" AND labels @> ")
+                    .push_bind(label_object(key, value));
+            }
+            Requirement::NotEquals(key, value) => {
+                builder
+                    .push(" AND NOT labels @> ")
+                    .push_bind(label_object(key, value));
+            }
+            Requirement::In(key, values) => {
+                builder
+                    .push(" AND labels ->> ")
+                    .push_bind(key.as_str())
+                    .push(" = ANY(")
+                    .push_bind(values)
+                    .push(")");
+            }
+            Requirement::NotIn(key, values) => {
+                builder
+                    .push(" AND NOT COALESCE(labels ->> ")
+                    .push_bind(key.as_str())
+                    .push(" = ANY(")
+                    .push_bind(values)
+                    .push("), FALSE)");
+            }
+            Requirement::Exists(key) => {
+                builder.push(" AND labels ? ").push_bind(key.as_str());
+            }
+            Requirement::NotExists(key) => {
+                builder.push(" AND NOT labels ? ").push_bind(key.as_str());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(selector: &str) -> Result<Vec<Requirement>, String> {
+        selector.parse::<LabelSelector>().map(|selector| selector.0)
+    }
+
+    fn s(value: &str) -> String {
+        value.to_string()
+    }
+
+    #[test]
+    fn validates_keys_and_values() {
+        assert!(is_valid_label_key("team"));
+        assert!(is_valid_label_key("example.com/cost-center"));
+        assert!(!is_valid_label_key(""));
+        assert!(!is_valid_label_key("-team"));
+        assert!(!is_valid_label_key("Example.com/team"));
+        assert!(!is_valid_label_key("/team"));
+        assert!(!is_valid_label_key(&"a".repeat(64)));
+
+        assert!(is_valid_label_value(""));
+        assert!(is_valid_label_value("v1.2_beta"));
+        assert!(!is_valid_label_value("payments-"));
+        assert!(!is_valid_label_value("a b"));
+    }
+
+    #[test]
+    fn parses_selector_requirements() {
+        assert_eq!(
+            parse("team=payments, tier==prod,env!=dev,region in (eu, us),tier notin (dev),a,!b"),
+            Ok(vec![
+                Requirement::Equals(s("team"), s("payments")),
+                Requirement::Equals(s("tier"), s("prod")),
+                Requirement::NotEquals(s("env"), s("dev")),
+                Requirement::In(s("region"), vec![s("eu"), s("us")]),
+                Requirement::NotIn(s("tier"), vec![s("dev")]),
+                Requirement::Exists(s("a")),
+                Requirement::NotExists(s("b")),
+            ])
+        );
+        assert_eq!(parse(""), Ok(vec![]));
+        assert_eq!(
+            parse("example.com/team="),
+            Ok(vec![Requirement::Equals(s("example.com/team"), s(""))])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_selectors() {
+        for selector in [
+            "region in (eu,us",
+            "region in eu)",
+            "region in ((eu))",
+            "region within (eu)",
+            "region (eu)",
+            "team=pay ments",
+            "team=-payments",
+            "!",
+        ] {
+            assert!(parse(selector).is_err(), "{:?} should be rejected", selector);
+        }
+    }
+
+    #[test]
+    fn names_metric_labels() {
+        assert_eq!(
+            metric_label_name("example.com/cost-center"),
+            "label_example_com_cost_center"
+        );
+    }
+}
@@ -1,18 +1,59 @@
 mod api;
+mod audit;
+mod auth;
+mod changes;
 mod config;
+mod context;
+mod crypto;
 mod db;
+mod enforcement;
+mod export;
+mod idempotency;
+mod idle;
+mod jobs;
+mod labels;
+mod metering;
 mod metrics;
 mod middleware;
+mod net;
+mod organizations;
+mod plans;
 mod platform;
+mod prober;
+mod provisioner;
+mod quotas;
+mod reconcile;
+mod schedules;
+mod tasks;
+mod tenant_stats;
+mod validation;
+mod webhooks;
 
 use anyhow::Result;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tracing::info;
 
 use api::create_router;
+use auth::Authenticator;
+use changes::ChangeFeed;
 use config::Config;
+use context::TrustedProxies;
+use crypto::Keyring;
 use db::Database;
+use enforcement::EnforcingProvisioner;
+use idempotency::IdempotencyStore;
+use idle::IdleDetector;
+use jobs::JobQueue;
+use metering::Meter;
 use metrics::Metrics;
+use platform::ProjectStatus;
+use prober::Prober;
+use quotas::QuotaEvaluator;
+use reconcile::Reconciler;
+use schedules::Scheduler;
+use tenant_stats::TenantStatsCollector;
+use webhooks::WebhookDispatcher;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -44,13 +85,25 @@ async fn main() -> Result<()> {
     info!("Database URL starts with postgres://: {}", config.database.url.starts_with("postgres://"));
 
     // Initialize metrics
-    let metrics = Metrics::new()?;
+    let metrics = Metrics::new(&config.metrics)?;
     info!("Metrics initialized");
 
+    // Load encryption keys for secrets stored in the database
+    let keyring = Keyring::from_config(&config.encryption)?;
+    match &keyring {
+        Some(keyring) => info!(
+            "Encryption at rest enabled (active key: {})",
+            keyring.active_key_id()
+        ),
+        None => tracing::warn!(
+            "ENCRYPTION_KEYS is not set; project database URLs will be stored in plaintext"
+        ),
+    }
+
     // Initialize database
     info!("Attempting to connect to database...");
     let database = Arc::new(
-        Database::new(&config.database.url, config.database.max_connections)
+        Database::new(&config.database.url, config.database.max_connections, keyring)
             .await
             .map_err(|e| {
                 tracing::error!("Database connection failed: {}", e);
@@ -70,15 +123,183 @@ async fn main() -> Result<()> {
         }
     });
 
+    // Probe the health of every active project
+    if config.probe.enabled {
+        let prober = Prober::new(database.clone(), metrics.clone(), config.probe.clone())?;
+        tasks::spawn_supervised("health-prober", move || prober.clone().run());
+        info!(
+            "Health prober started (every {}s)",
+            config.probe.interval_secs
+        );
+    }
+
+    // Collect statistics from every active project's database
+    if config.tenant_stats.enabled {
+        let collector = TenantStatsCollector::new(
+            database.clone(),
+            metrics.clone(),
+            config.tenant_stats.clone(),
+        );
+        tasks::spawn_supervised("tenant-stats", move || collector.clone().run());
+        info!(
+            "Tenant stats collector started (every {}s)",
+            config.tenant_stats.interval_secs
+        );
+    }
+
+    // Compare collected usage with the limits of each project's plan
+    if config.quota.enabled {
+        let evaluator =
+            QuotaEvaluator::new(database.clone(), metrics.clone(), config.quota.clone());
+        tasks::spawn_supervised("quota-evaluator", move || evaluator.clone().run());
+        info!(
+            "Quota evaluator started (every {}s)",
+            config.quota.interval_secs
+        );
+    }
+
+    // Sample every project's usage into hourly and daily records
+    if config.metering.enabled {
+        let meter = Meter::new(database.clone(), config.metering.clone());
+        tasks::spawn_supervised("metering", move || meter.clone().run());
+        info!(
+            "Metering started (every {}s)",
+            config.metering.interval_secs
+        );
+    }
+
+    // Deliver queued lifecycle events to webhook subscriptions
+    if config.webhooks.enabled {
+        let dispatcher =
+            WebhookDispatcher::new(database.clone(), metrics.clone(), config.webhooks.clone())?;
+        tasks::spawn_supervised("webhook-dispatcher", move || dispatcher.clone().run());
+        info!(
+            "Webhook dispatcher started (every {}s)",
+            config.webhooks.poll_interval_secs
+        );
+    }
+
+    // Project changes pushed to stream subscribers
+    let changes = ChangeFeed::new();
+
+    // Carries out lifecycle transitions on the infrastructure behind projects
+    let mut provisioner = provisioner::from_config(&config.provisioner)?;
+    info!("Provisioner: {}", provisioner.name());
+    if config.enforcement.enabled {
+        provisioner = EnforcingProvisioner::wrap(
+            provisioner,
+            database.clone(),
+            metrics.clone(),
+            config.enforcement.clone(),
+        );
+        info!("Suspension enforcement on project databases enabled");
+    }
+
+    // Run long-running operations, such as lifecycle transitions requested with
+    // `Prefer: respond-async`, in the background
+    let jobs = JobQueue::new(
+        database.clone(),
+        metrics.clone(),
+        changes.clone(),
+        provisioner.clone(),
+        config.jobs.clone(),
+    );
+    for _ in 0..config.jobs.workers {
+        let jobs = jobs.clone();
+        tasks::spawn_supervised("job-worker", move || jobs.clone().work());
+    }
+    {
+        let jobs = jobs.clone();
+        tasks::spawn_supervised("job-maintenance", move || jobs.clone().maintain());
+    }
+    let reconciler = Reconciler::new(
+        database.clone(),
+        changes.clone(),
+        jobs.clone(),
+        metrics.clone(),
+    );
+    info!("Job queue started ({} workers)", config.jobs.workers);
+
+    // Run scheduled suspends and resumes of projects
+    if config.schedules.enabled {
+        let scheduler = Scheduler::new(
+            database.clone(),
+            metrics.clone(),
+            changes.clone(),
+            provisioner.clone(),
+            config.schedules.clone(),
+        );
+        tasks::spawn_supervised("scheduler", move || scheduler.clone().run());
+        info!(
+            "Scheduler started (every {}s)",
+            config.schedules.poll_interval_secs
+        );
+    }
+
+    // Suspend projects that have been idle for longer than their plan allows. The
+    // detector also serves the dry-run report, so it exists even when disabled.
+    let idle = IdleDetector::new(
+        database.clone(),
+        metrics.clone(),
+        changes.clone(),
+        provisioner.clone(),
+        config.idle.clone(),
+        std::time::Duration::from_secs(config.tenant_stats.interval_secs),
+    );
+    if config.idle.enabled && config.tenant_stats.enabled {
+        let idle = idle.clone();
+        tasks::spawn_supervised("idle-detector", move || idle.clone().run());
+        info!(
+            "Idle detector started (every {}s)",
+            config.idle.interval_secs
+        );
+    } else if config.idle.enabled {
+        tracing::warn!("Idle detection needs TENANT_STATS_ENABLED; idle projects will not be suspended");
+    }
+
+    // API key authentication
+    if !config.auth.enabled {
+        tracing::warn!("AUTH_ENABLED=false: the control plane API accepts unauthenticated requests");
+    }
+    let authenticator = Arc::new(Authenticator::new(&config.auth, database.clone()));
+
+    // Replay of responses to requests sent with an Idempotency-Key
+    let idempotency = IdempotencyStore::new(database.clone(), config.idempotency.clone());
+    {
+        let idempotency = idempotency.clone();
+        tasks::spawn_supervised("idempotency-pruner", move || idempotency.clone().run());
+    }
+
+    // Reverse proxies whose X-Forwarded-For is believed for audit source IPs
+    let trusted_proxies = Arc::new(TrustedProxies::parse(&config.server.trusted_proxies)?);
+    if trusted_proxies.is_empty() {
+        info!("No TRUSTED_PROXIES: audit entries record the peer address as source IP");
+    }
+
     // Create router
-    let app = create_router(metrics, database);
+    let app = create_router(
+        metrics,
+        database,
+        authenticator,
+        idempotency,
+        changes,
+        idle,
+        provisioner,
+        jobs,
+        reconciler,
+        trusted_proxies,
+    );
 
     // Start server
     let addr = format!("{}:{}", config.server.host, config.server.port);
     info!("Starting TelemetryWatch server on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -92,63 +313,47 @@ async fn update_metrics(metrics: &Arc<Metrics>, db: &Arc<Database>) {
 
     // Update platform projects metrics
     if let Ok(projects) = db.list_platform_projects().await {
-        // Reset all platform_projects gauges to 0 first
-        // We need to reset all possible label combinations, but Prometheus doesn't expose
-        // a way to iterate over existing labels. Instead, we'll reset the totals and
-        // only set gauges for current projects (old statuses will remain but that's okay
-        // as long as we're consistent)
+        // Clear per-project series first so that projects which changed status
+        // (or were deleted) don't keep reporting their old label combination
+        metrics.platform_projects.reset();
 
         // Count projects by status and plan
-        let mut status_plan_counts: std::collections::HashMap<(String, String), i32> =
+        let mut status_plan_counts: std::collections::HashMap<(ProjectStatus, String), i32> =
             std::collections::HashMap::new();
 
-        // Group projects by slug to reset old statuses
-        let mut projects_by_slug: std::collections::HashMap<String, &crate::platform::PlatformProject> = 
-            std::collections::HashMap::new();
-        
-        for project in &projects {
-            // If we've seen this slug before, reset all its old status combinations
-            if let Some(existing) = projects_by_slug.get(&project.slug) {
-                // Reset old status for this slug
-                metrics
-                    .platform_projects
-                    .with_label_values(&[
-                        &existing.slug,
-                        &existing.status,
-                        &existing.plan,
-                        &existing.region,
-                    ])
-                    .set(0.0);
-            }
-            projects_by_slug.insert(project.slug.clone(), project);
-        }
-
         // Now set current statuses
         for project in &projects {
-            // Set individual project gauge (current status only)
+            // Set individual project gauge (current status only), with the
+            // promoted labels as empty strings where the project lacks them
+            let mut labels = vec![
+                project.slug.as_str(),
+                project.status.as_str(),
+                project.plan.as_str(),
+                project.region.as_str(),
+            ];
+            for key in &metrics.promoted_project_labels {
+                labels.push(project.labels.get(key).map_or("", String::as_str));
+            }
             metrics
                 .platform_projects
-                .with_label_values(&[
-                    &project.slug,
-                    &project.status,
-                    &project.plan,
-                    &project.region,
-                ])
+                .with_label_values(&labels)
                 .set(1.0);
 
             // Count for totals
-            let key = (project.status.clone(), project.plan.clone());
+            let key = (project.status, project.plan.clone());
             *status_plan_counts.entry(key).or_insert(0) += 1;
         }
 
-        // Reset totals to 0 first, then set current counts
-        // Note: We can't easily reset all label combinations, but we can reset totals
-        // by setting all known combinations to 0, then setting current ones
-        for status in &["active", "suspended"] {
-            for plan in &["dev", "pro", "enterprise"] {
+        // Reset totals, then report 0 for every status and catalog plan so empty
+        // combinations show up, and finally set the current counts. Plans removed
+        // from the catalog drop out with the reset.
+        metrics.platform_projects_total.reset();
+        let plans = db.list_plans().await.unwrap_or_default();
+        for status in ProjectStatus::ALL {
+            for plan in &plans {
                 metrics
                     .platform_projects_total
-                    .with_label_values(&[status, plan])
+                    .with_label_values(&[status.as_str(), &plan.name])
                     .set(0.0);
             }
         }
@@ -157,7 +362,7 @@ async fn update_metrics(metrics: &Arc<Metrics>, db: &Arc<Database>) {
         for ((status, plan), count) in status_plan_counts {
             metrics
                 .platform_projects_total
-                .with_label_values(&[&status, &plan])
+                .with_label_values(&[status.as_str(), &plan])
                 .set(count as f64);
         }
     }
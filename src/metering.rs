@@ -0,0 +1,423 @@
+use chrono::{DateTime, Duration as ChronoDuration, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::MeteringConfig;
+use crate::db::Database;
+use crate::organizations::{push_org_access, OrgAccess};
+use crate::platform::string_enum;
+use crate::validation::ValidationErrors;
+
+string_enum! {
+    /// Length of the period a usage record covers (UTC hours or days)
+    pub enum UsageGranularity {
+        Hour => "hour",
+        Day => "day",
+    }
+}
+
+string_enum! {
+    /// Response format of usage listings
+    pub enum UsageFormat {
+        Json => "json",
+        Csv => "csv",
+    }
+}
+
+impl UsageGranularity {
+    /// Longest range that can be listed at once, to bound response sizes.
+    fn max_range(self) -> ChronoDuration {
+        match self {
+            UsageGranularity::Hour => ChronoDuration::days(31),
+            UsageGranularity::Day => ChronoDuration::days(731),
+        }
+    }
+}
+
+/// Usage of a project during one hour or day, while it was on `plan`. A project
+/// that changes plan within a period has one record per plan.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct UsageRecord {
+    pub project_id: i64,
+    #[schema(example = "acme-ecommerce")]
+    pub slug: String,
+    #[schema(example = "pro")]
+    pub plan: String,
+    pub granularity: UsageGranularity,
+    /// Start of the period (UTC)
+    pub period_start: DateTime<Utc>,
+    /// Number of metering samples taken in the period
+    pub samples: i32,
+    /// Hours the project was `active`
+    pub active_hours: f64,
+    /// Hours the project was `suspended`
+    pub suspended_hours: f64,
+    /// Connections to the project database integrated over time
+    pub connection_hours: f64,
+    /// Largest database size seen in the period, if it was collected
+    pub db_size_bytes_max: Option<i64>,
+    /// API requests reported for the period
+    pub api_requests: i64,
+}
+
+/// Query parameters for listing usage records.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UsageQuery {
+    /// Start of the range, inclusive (RFC 3339; default 30 days before `to`)
+    pub from: Option<DateTime<Utc>>,
+    /// End of the range, exclusive (RFC 3339; default now)
+    pub to: Option<DateTime<Utc>>,
+    /// `hour` or `day` (default `day`). Hourly ranges are limited to 31 days, daily ones to 731.
+    pub granularity: Option<UsageGranularity>,
+    /// `json` (default) or `csv`
+    pub format: Option<UsageFormat>,
+}
+
+/// A [`UsageQuery`] that has passed validation.
+#[derive(Debug, Clone, Copy)]
+pub struct UsageRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub granularity: UsageGranularity,
+}
+
+impl UsageQuery {
+    pub fn validate(&self) -> Result<UsageRange, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let granularity = self.granularity.unwrap_or(UsageGranularity::Day);
+        let to = self.to.unwrap_or_else(Utc::now);
+        let from = self.from.unwrap_or(to - ChronoDuration::days(30));
+
+        if from >= to {
+            errors.add("from", "must be before `to`");
+        } else if to - from > granularity.max_range() {
+            errors.add(
+                "from",
+                format!(
+                    "range is limited to {} days for granularity {}",
+                    granularity.max_range().num_days(),
+                    granularity
+                ),
+            );
+        }
+
+        errors.into_result(UsageRange {
+            from,
+            to,
+            granularity,
+        })
+    }
+
+    pub fn format(&self) -> UsageFormat {
+        self.format.unwrap_or(UsageFormat::Json)
+    }
+}
+
+/// Request body for reporting API requests served by a project.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct ReportApiRequests {
+    /// Requests served since the previous report
+    #[schema(example = 1250)]
+    pub count: i64,
+}
+
+impl ReportApiRequests {
+    pub fn validate(&self) -> Result<i64, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if !(0..=1_000_000_000).contains(&self.count) {
+            errors.add("count", "must be between 0 and 1000000000");
+        }
+        errors.into_result(self.count)
+    }
+}
+
+/// Renders usage records as CSV, one row per record, with a header line. Every
+/// field is a number, timestamp, slug or plan name, so none needs quoting.
+pub fn usage_csv(records: &[UsageRecord]) -> String {
+    let mut csv = String::from(
+        "project_id,slug,plan,granularity,period_start,samples,active_hours,suspended_hours,\
+         connection_hours,db_size_bytes_max,api_requests\n",
+    );
+    for r in records {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{:.4},{:.4},{:.4},{},{}",
+            r.project_id,
+            r.slug,
+            r.plan,
+            r.granularity,
+            r.period_start.to_rfc3339_opts(SecondsFormat::Secs, true),
+            r.samples,
+            r.active_hours,
+            r.suspended_hours,
+            r.connection_hours,
+            r.db_size_bytes_max
+                .map(|v| v.to_string())
+                .unwrap_or_default(),
+            r.api_requests,
+        );
+    }
+    csv
+}
+
+/// Samples every project's status and resource usage on a schedule and adds
+/// the samples to its hourly and daily `usage_records`.
+pub struct Meter {
+    db: Arc<Database>,
+    config: MeteringConfig,
+}
+
+impl Meter {
+    pub fn new(db: Arc<Database>, config: MeteringConfig) -> Arc<Self> {
+        Arc::new(Self { db, config })
+    }
+
+    /// Takes a sample every `interval_secs`. Meant to run under
+    /// [`crate::tasks::spawn_supervised`].
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
+        let interval_secs = self.config.interval_secs;
+        let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        let mut last_sample: Option<tokio::time::Instant> = None;
+        loop {
+            let now = interval.tick().await;
+            // A sample stands for the time since the previous one. Late ticks are
+            // credited in full, but never more than two intervals' worth.
+            let elapsed = last_sample
+                .map(|last| now - last)
+                .unwrap_or(Duration::from_secs(interval_secs))
+                .min(Duration::from_secs(interval_secs * 2));
+            last_sample = Some(now);
+
+            let sampled = self.db.record_usage_sample(elapsed).await?;
+            tracing::debug!("Metered {} projects over {}s", sampled, elapsed.as_secs());
+            self.db
+                .prune_usage_records(self.config.daily_retention_days)
+                .await?;
+        }
+    }
+}
+
+impl Database {
+    /// Adds one sample covering `elapsed` to the current hourly and daily record of
+    /// every project that is not deleted. Returns the number of projects sampled.
+    ///
+    /// Connection-hours come from the last connection count collected from the
+    /// project database and only accrue while the project is active.
+    async fn record_usage_sample(&self, elapsed: Duration) -> anyhow::Result<u64> {
+        let hours = elapsed.as_secs_f64() / 3600.0;
+        let mut tx = self.pool.begin().await?;
+
+        let mut sampled = 0;
+        for granularity in UsageGranularity::ALL {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO usage_records (project_id, plan, granularity, period_start, samples,
+                                           active_hours, suspended_hours, connection_hours,
+                                           db_size_bytes_max)
+                SELECT p.id, p.plan, $1,
+                       date_trunc($1, NOW() AT TIME ZONE 'UTC') AT TIME ZONE 'UTC',
+                       1,
+                       CASE WHEN p.status = 'active' THEN $2 ELSE 0 END,
+                       CASE WHEN p.status = 'suspended' THEN $2 ELSE 0 END,
+                       CASE WHEN p.status = 'active' THEN COALESCE(u.connections, 0) * $2 ELSE 0 END,
+                       u.db_size_bytes
+                FROM platform_projects p
+                LEFT JOIN platform_project_usage u ON u.project_id = p.id
+                WHERE p.status <> 'deleted'
+                ON CONFLICT (project_id, granularity, period_start, plan) DO UPDATE SET
+                    samples = usage_records.samples + 1,
+                    active_hours = usage_records.active_hours + EXCLUDED.active_hours,
+                    suspended_hours = usage_records.suspended_hours + EXCLUDED.suspended_hours,
+                    connection_hours = usage_records.connection_hours + EXCLUDED.connection_hours,
+                    db_size_bytes_max = GREATEST(usage_records.db_size_bytes_max,
+                                                 EXCLUDED.db_size_bytes_max),
+                    updated_at = NOW()
+                "#,
+            )
+            .bind(granularity)
+            .bind(hours)
+            .execute(&mut *tx)
+            .await?;
+            sampled = result.rows_affected();
+        }
+
+        tx.commit().await?;
+        Ok(sampled)
+    }
+
+    /// Adds `count` API requests to the project's current hourly and daily record.
+    /// Returns `false` if the project does not exist or is deleted.
+    pub async fn record_api_requests(&self, project_id: i64, count: i64) -> anyhow::Result<bool> {
+        let mut tx = self.pool.begin().await?;
+
+        let mut recorded = false;
+        for granularity in UsageGranularity::ALL {
+            let result = sqlx::query(
+                r#"
+                INSERT INTO usage_records (project_id, plan, granularity, period_start, api_requests)
+                SELECT p.id, p.plan, $2,
+                       date_trunc($2, NOW() AT TIME ZONE 'UTC') AT TIME ZONE 'UTC', $3
+                FROM platform_projects p
+                WHERE p.id = $1 AND p.status <> 'deleted'
+                ON CONFLICT (project_id, granularity, period_start, plan) DO UPDATE SET
+                    api_requests = usage_records.api_requests + EXCLUDED.api_requests,
+                    updated_at = NOW()
+                "#,
+            )
+            .bind(project_id)
+            .bind(granularity)
+            .bind(count)
+            .execute(&mut *tx)
+            .await?;
+            recorded = result.rows_affected() > 0;
+        }
+
+        tx.commit().await?;
+        Ok(recorded)
+    }
+
+    /// Deletes hourly records older than the retention of the project's plan, and
+    /// daily records older than `daily_retention_days`.
+    async fn prune_usage_records(&self, daily_retention_days: i64) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM usage_records r
+            USING plans pl
+            WHERE r.granularity = 'hour'
+              AND pl.name = r.plan
+              AND r.period_start < NOW() - make_interval(days => pl.retention_days)
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            DELETE FROM usage_records
+            WHERE granularity = 'day' AND period_start < NOW() - make_interval(days => $1)
+            "#,
+        )
+        .bind(daily_retention_days as i32)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Usage records in `range`, oldest first, of one project or of every project
+    /// visible with `access`.
+    pub async fn list_usage_records(
+        &self,
+        project_id: Option<i64>,
+        range: &UsageRange,
+        access: &OrgAccess,
+    ) -> anyhow::Result<Vec<UsageRecord>> {
+        let mut query: QueryBuilder<Postgres> = QueryBuilder::new(
+            r#"
+            SELECT r.project_id, p.slug, r.plan, r.granularity, r.period_start, r.samples,
+                   r.active_hours, r.suspended_hours, r.connection_hours, r.db_size_bytes_max,
+                   r.api_requests
+            FROM usage_records r
+            JOIN platform_projects p ON p.id = r.project_id
+            WHERE r.granularity = "#,
+        );
+        query
+            .push_bind(range.granularity)
+            .push(" AND r.period_start >= ")
+            .push_bind(range.from)
+            .push(" AND r.period_start < ")
+            .push_bind(range.to);
+        if let Some(project_id) = project_id {
+            query.push(" AND r.project_id = ").push_bind(project_id);
+        }
+        push_org_access(&mut query, access);
+        query.push(" ORDER BY r.period_start, p.slug, r.plan");
+
+        let records = query
+            .build_query_as::<UsageRecord>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(plan: &str, db_size_bytes_max: Option<i64>) -> UsageRecord {
+        UsageRecord {
+            project_id: 7,
+            slug: "acme".to_string(),
+            plan: plan.to_string(),
+            granularity: UsageGranularity::Hour,
+            period_start: "2024-05-01T12:00:00Z".parse().unwrap(),
+            samples: 60,
+            active_hours: 0.75,
+            suspended_hours: 0.25,
+            connection_hours: 2.0 / 3.0,
+            db_size_bytes_max,
+            api_requests: 1250,
+        }
+    }
+
+    #[test]
+    fn renders_usage_csv() {
+        assert_eq!(
+            usage_csv(&[]),
+            "project_id,slug,plan,granularity,period_start,samples,active_hours,suspended_hours,\
+             connection_hours,db_size_bytes_max,api_requests\n"
+        );
+
+        let csv = usage_csv(&[record("free", Some(1024)), record("pro", None)]);
+        let rows: Vec<&str> = csv.lines().skip(1).collect();
+        assert_eq!(
+            rows,
+            [
+                "7,acme,free,hour,2024-05-01T12:00:00Z,60,0.7500,0.2500,0.6667,1024,1250",
+                "7,acme,pro,hour,2024-05-01T12:00:00Z,60,0.7500,0.2500,0.6667,,1250",
+            ]
+        );
+    }
+
+    fn range_error(granularity: UsageGranularity, days: i64) -> Option<String> {
+        let to: DateTime<Utc> = "2024-05-01T00:00:00Z".parse().unwrap();
+        let query = UsageQuery {
+            from: Some(to - ChronoDuration::days(days)),
+            to: Some(to),
+            granularity: Some(granularity),
+            format: None,
+        };
+        query.validate().err().map(|errors| {
+            assert_eq!(errors.errors.len(), 1);
+            assert_eq!(errors.errors[0].field, "from");
+            errors.errors[0].message.clone()
+        })
+    }
+
+    #[test]
+    fn limits_usage_ranges() {
+        assert_eq!(range_error(UsageGranularity::Hour, 31), None);
+        assert_eq!(
+            range_error(UsageGranularity::Hour, 32).as_deref(),
+            Some("range is limited to 31 days for granularity hour")
+        );
+        assert_eq!(range_error(UsageGranularity::Day, 731), None);
+        assert_eq!(
+            range_error(UsageGranularity::Day, 732).as_deref(),
+            Some("range is limited to 731 days for granularity day")
+        );
+        assert_eq!(
+            range_error(UsageGranularity::Day, 0).as_deref(),
+            Some("must be before `to`")
+        );
+    }
+}
@@ -4,6 +4,9 @@ use prometheus::{
 };
 use std::sync::Arc;
 
+use crate::config::MetricsConfig;
+use crate::labels::{is_valid_label_key, metric_label_name};
+
 pub struct Metrics {
     pub registry: Registry,
     pub http_requests_total: IntCounterVec,
@@ -28,11 +31,39 @@ pub struct Metrics {
     pub sla_violations_total: IntCounterVec,
     // Platform projects metrics
     pub platform_projects: GaugeVec,
+    /// Project label keys promoted to `label_*` labels of `platform_projects`, in
+    /// the order they follow the fixed labels
+    pub promoted_project_labels: Vec<String>,
     pub platform_projects_total: GaugeVec,
+    // Project health probes
+    pub platform_project_up: GaugeVec,
+    pub platform_project_probe_duration_seconds: GaugeVec,
+    // Tenant database statistics
+    pub platform_project_db_stats_up: GaugeVec,
+    pub platform_project_db_transactions: GaugeVec,
+    pub platform_project_db_blocks: GaugeVec,
+    pub platform_project_db_deadlocks: GaugeVec,
+    pub platform_project_db_connections: GaugeVec,
+    pub platform_project_db_size_bytes: GaugeVec,
+    pub platform_project_db_longest_transaction_seconds: GaugeVec,
+    // Plan quotas
+    pub platform_project_quota_utilization: GaugeVec,
+    // Webhooks
+    pub webhook_delivery_attempts_total: IntCounterVec,
+    pub webhook_deliveries_failed_total: IntCounterVec,
+    pub webhook_deliveries_pending: Gauge,
+    pub webhook_delivery_duration_seconds: Histogram,
+    pub project_schedule_runs_total: IntCounterVec,
+    pub platform_projects_auto_suspended_total: IntCounterVec,
+    pub project_enforcement_steps_total: IntCounterVec,
+    pub jobs_total: IntCounterVec,
+    pub jobs_queued: Gauge,
+    // Audit log
+    pub audit_write_failures_total: IntCounter,
 }
 
 impl Metrics {
-    pub fn new() -> anyhow::Result<Arc<Self>> {
+    pub fn new(config: &MetricsConfig) -> anyhow::Result<Arc<Self>> {
         let registry = Registry::new();
 
         let http_requests_total = IntCounterVec::new(
@@ -155,13 +186,32 @@ impl Metrics {
         )?;
         registry.register(Box::new(sla_violations_total.clone()))?;
 
-        // Platform projects metrics
+        // Platform projects metrics. Promoted project labels become extra labels,
+        // skipping keys that are invalid or map to a label name already taken.
+        let mut promoted_project_labels = Vec::new();
+        let mut project_label_names = vec![
+            "slug".to_string(),
+            "status".to_string(),
+            "plan".to_string(),
+            "region".to_string(),
+        ];
+        for key in &config.promoted_project_labels {
+            let name = metric_label_name(key);
+            if !is_valid_label_key(key) || project_label_names.contains(&name) {
+                tracing::warn!("Not promoting project label '{}' to a metric label", key);
+                continue;
+            }
+            promoted_project_labels.push(key.clone());
+            project_label_names.push(name);
+        }
+        let project_label_names: Vec<&str> =
+            project_label_names.iter().map(String::as_str).collect();
         let platform_projects = GaugeVec::new(
             Opts::new(
                 "platform_projects",
                 "Platform project status (1 = exists, 0 = doesn't exist)",
             ),
-            &["slug", "status", "plan", "region"],
+            &project_label_names,
         )?;
         registry.register(Box::new(platform_projects.clone()))?;
 
@@ -174,6 +224,186 @@ impl Metrics {
         )?;
         registry.register(Box::new(platform_projects_total.clone()))?;
 
+        // Project health probes
+        let platform_project_up = GaugeVec::new(
+            Opts::new(
+                "platform_project_up",
+                "Whether the last probe reached both the project's database and API (1 = up, 0 = down)",
+            ),
+            &["slug"],
+        )?;
+        registry.register(Box::new(platform_project_up.clone()))?;
+
+        let platform_project_probe_duration_seconds = GaugeVec::new(
+            Opts::new(
+                "platform_project_probe_duration_seconds",
+                "Duration of the last probe of a project, per check (database or api)",
+            ),
+            &["slug", "check"],
+        )?;
+        registry.register(Box::new(platform_project_probe_duration_seconds.clone()))?;
+
+        // Tenant database statistics
+        let platform_project_db_stats_up = GaugeVec::new(
+            Opts::new(
+                "platform_project_db_stats_up",
+                "Whether the last statistics collection from the project's database succeeded (1 = yes, 0 = no)",
+            ),
+            &["slug"],
+        )?;
+        registry.register(Box::new(platform_project_db_stats_up.clone()))?;
+
+        let platform_project_db_transactions = GaugeVec::new(
+            Opts::new(
+                "platform_project_db_transactions",
+                "Transactions committed or rolled back in the project's database since its stats reset",
+            ),
+            &["slug", "result"],
+        )?;
+        registry.register(Box::new(platform_project_db_transactions.clone()))?;
+
+        let platform_project_db_blocks = GaugeVec::new(
+            Opts::new(
+                "platform_project_db_blocks",
+                "Blocks found in the buffer cache (hit) or read from disk (read) by the project's database",
+            ),
+            &["slug", "source"],
+        )?;
+        registry.register(Box::new(platform_project_db_blocks.clone()))?;
+
+        let platform_project_db_deadlocks = GaugeVec::new(
+            Opts::new(
+                "platform_project_db_deadlocks",
+                "Deadlocks detected in the project's database since its stats reset",
+            ),
+            &["slug"],
+        )?;
+        registry.register(Box::new(platform_project_db_deadlocks.clone()))?;
+
+        let platform_project_db_connections = GaugeVec::new(
+            Opts::new(
+                "platform_project_db_connections",
+                "Connections to the project's database by state",
+            ),
+            &["slug", "state"],
+        )?;
+        registry.register(Box::new(platform_project_db_connections.clone()))?;
+
+        let platform_project_db_size_bytes = GaugeVec::new(
+            Opts::new(
+                "platform_project_db_size_bytes",
+                "Size of the project's database in bytes",
+            ),
+            &["slug"],
+        )?;
+        registry.register(Box::new(platform_project_db_size_bytes.clone()))?;
+
+        let platform_project_db_longest_transaction_seconds = GaugeVec::new(
+            Opts::new(
+                "platform_project_db_longest_transaction_seconds",
+                "Age of the oldest open transaction in the project's database",
+            ),
+            &["slug"],
+        )?;
+        registry.register(Box::new(platform_project_db_longest_transaction_seconds.clone()))?;
+
+        // Plan quotas
+        let platform_project_quota_utilization = GaugeVec::new(
+            Opts::new(
+                "platform_project_quota_utilization",
+                "Usage of a project as a fraction of its plan's limit (above 1 = quota exceeded)",
+            ),
+            &["slug", "plan", "resource"],
+        )?;
+        registry.register(Box::new(platform_project_quota_utilization.clone()))?;
+
+        // Webhooks
+        let webhook_delivery_attempts_total = IntCounterVec::new(
+            Opts::new(
+                "webhook_delivery_attempts_total",
+                "Webhook delivery attempts by event type and result (success or failure)",
+            ),
+            &["event_type", "result"],
+        )?;
+        registry.register(Box::new(webhook_delivery_attempts_total.clone()))?;
+
+        let webhook_deliveries_failed_total = IntCounterVec::new(
+            Opts::new(
+                "webhook_deliveries_failed_total",
+                "Webhook deliveries given up on after their last attempt failed",
+            ),
+            &["event_type"],
+        )?;
+        registry.register(Box::new(webhook_deliveries_failed_total.clone()))?;
+
+        let webhook_deliveries_pending = Gauge::new(
+            "webhook_deliveries_pending",
+            "Webhook deliveries waiting for their first attempt or a retry",
+        )?;
+        registry.register(Box::new(webhook_deliveries_pending.clone()))?;
+
+        let webhook_delivery_duration_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "webhook_delivery_duration_seconds",
+                "Duration of webhook delivery attempts in seconds",
+            )
+            .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]),
+        )?;
+        registry.register(Box::new(webhook_delivery_duration_seconds.clone()))?;
+
+        // Schedules
+        let project_schedule_runs_total = IntCounterVec::new(
+            Opts::new(
+                "project_schedule_runs_total",
+                "Runs of project schedules by action and outcome",
+            ),
+            &["action", "outcome"],
+        )?;
+        registry.register(Box::new(project_schedule_runs_total.clone()))?;
+
+        // Idle detection
+        let platform_projects_auto_suspended_total = IntCounterVec::new(
+            Opts::new(
+                "platform_projects_auto_suspended_total",
+                "Projects suspended automatically for being idle, by plan",
+            ),
+            &["plan"],
+        )?;
+        registry.register(Box::new(platform_projects_auto_suspended_total.clone()))?;
+
+        // Suspension enforcement on project databases
+        let project_enforcement_steps_total = IntCounterVec::new(
+            Opts::new(
+                "project_enforcement_steps_total",
+                "Steps of blocking and restoring access to project databases, by action, step and outcome",
+            ),
+            &["action", "step", "outcome"],
+        )?;
+        registry.register(Box::new(project_enforcement_steps_total.clone()))?;
+
+        // Background jobs
+        let jobs_total = IntCounterVec::new(
+            Opts::new(
+                "jobs_total",
+                "Attempts of background jobs by kind and outcome",
+            ),
+            &["kind", "outcome"],
+        )?;
+        registry.register(Box::new(jobs_total.clone()))?;
+
+        let jobs_queued = Gauge::new(
+            "jobs_queued",
+            "Background jobs waiting for their first attempt or a retry",
+        )?;
+        registry.register(Box::new(jobs_queued.clone()))?;
+
+        // Audit log
+        let audit_write_failures_total = IntCounter::new(
+            "audit_write_failures_total",
+            "Audit entries that could not be written",
+        )?;
+        registry.register(Box::new(audit_write_failures_total.clone()))?;
+
         Ok(Arc::new(Self {
             registry,
             http_requests_total,
@@ -192,7 +422,28 @@ impl Metrics {
             endpoint_error_rate,
             sla_violations_total,
             platform_projects,
+            promoted_project_labels,
             platform_projects_total,
+            platform_project_up,
+            platform_project_probe_duration_seconds,
+            platform_project_db_stats_up,
+            platform_project_db_transactions,
+            platform_project_db_blocks,
+            platform_project_db_deadlocks,
+            platform_project_db_connections,
+            platform_project_db_size_bytes,
+            platform_project_db_longest_transaction_seconds,
+            platform_project_quota_utilization,
+            webhook_delivery_attempts_total,
+            webhook_deliveries_failed_total,
+            webhook_deliveries_pending,
+            webhook_delivery_duration_seconds,
+            project_schedule_runs_total,
+            platform_projects_auto_suspended_total,
+            project_enforcement_steps_total,
+            jobs_total,
+            jobs_queued,
+            audit_write_failures_total,
         }))
     }
 
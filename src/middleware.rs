@@ -1,14 +1,208 @@
 use axum::{
-    extract::State,
-    http::Request,
+    body::{to_bytes, Body},
+    extract::{FromRequestParts, State},
+    http::{header, HeaderValue, Method, Request, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
+    Json,
 };
 use std::sync::Arc;
 use std::time::Instant;
 
+use crate::auth::{AuthError, Authenticator};
+use crate::context::{RequestContext, REQUEST_ID_HEADER};
+use crate::idempotency::{
+    is_valid_key, request_hash, Claim, IdempotencyStore, StoredResponse, IDEMPOTENCY_KEY_HEADER,
+};
 use crate::metrics::Metrics;
 
+/// Largest request body accepted with an `Idempotency-Key` (axum's default body limit).
+const IDEMPOTENT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Makes sure every request carries an `X-Request-Id` (generating one if the
+/// client didn't send it) and echoes it back on the response.
+pub async fn request_id_middleware(
+    mut request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    let request_id = match request.headers().get(REQUEST_ID_HEADER) {
+        Some(value) if !value.is_empty() => value.clone(),
+        _ => {
+            let generated = HeaderValue::from_str(&uuid::Uuid::new_v4().to_string())
+                .expect("UUIDs are valid header values");
+            request
+                .headers_mut()
+                .insert(REQUEST_ID_HEADER, generated.clone());
+            generated
+        }
+    };
+
+    let mut response = next.run(request).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    response
+}
+
+/// Resolves the caller's API key (`Authorization: Bearer <key>` or `X-API-Key`)
+/// and rejects requests whose key lacks the scope the route requires. The
+/// caller's [`crate::auth::Identity`] is left in the request extensions.
+pub async fn auth_middleware(
+    State(auth): State<Arc<Authenticator>>,
+    mut request: Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    if !auth.enabled() {
+        return next.run(request).await;
+    }
+    let Some(scope) = auth.required_scope(request.method(), request.uri().path()) else {
+        return next.run(request).await;
+    };
+
+    let headers = request.headers();
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))
+        .map(str::trim);
+
+    let result = match auth.authenticate(token).await {
+        Ok(identity) if identity.has_scope(scope) => Ok(identity),
+        Ok(_) => Err(AuthError::Forbidden(scope)),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(identity) => {
+            request.extensions_mut().insert(identity);
+            next.run(request).await
+        }
+        Err(e) => {
+            let status = match e {
+                AuthError::Unauthenticated => StatusCode::UNAUTHORIZED,
+                AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+                AuthError::Database(ref e) => {
+                    tracing::error!("Failed to authenticate request: {}", e);
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to authenticate request")
+                        .into_response();
+                }
+            };
+            let mut response =
+                (status, Json(serde_json::json!({ "message": e.to_string() }))).into_response();
+            if status == StatusCode::UNAUTHORIZED {
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            response
+        }
+    }
+}
+
+/// Makes mutating API requests sent with an `Idempotency-Key` safe to retry. The
+/// first response for a caller's key is stored and replayed for later requests
+/// with the same key, method, path and body; a different request reusing the key
+/// is rejected with `422`, and one arriving while the first is still being
+/// handled with `409`. Server errors are not stored, so a retry runs again; nor
+/// are responses carrying secrets unless they can be encrypted.
+pub async fn idempotency_middleware(
+    State(store): State<Arc<IdempotencyStore>>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let mutating = matches!(
+        *request.method(),
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    );
+    let Some(key) = request.headers().get(IDEMPOTENCY_KEY_HEADER).cloned() else {
+        return next.run(request).await;
+    };
+    if !mutating || !request.uri().path().starts_with("/api/") {
+        return next.run(request).await;
+    }
+    let Some(key) = key.to_str().ok().filter(|k| is_valid_key(k)).map(str::to_string) else {
+        return message(
+            StatusCode::BAD_REQUEST,
+            "Idempotency-Key must be 1-255 visible ASCII characters",
+        );
+    };
+
+    let (mut parts, body) = request.into_parts();
+    let Ok(ctx) = RequestContext::from_request_parts(&mut parts, &()).await;
+    let body = match to_bytes(body, IDEMPOTENT_BODY_LIMIT).await {
+        Ok(body) => body,
+        Err(_) => return message(StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large"),
+    };
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or_default();
+    let hash = request_hash(&parts.method, path, &body);
+
+    let token = match store.claim(&ctx.actor, &key, &hash).await {
+        Ok(Claim::Claimed(token)) => token,
+        Ok(Claim::Completed(stored)) => return stored.replay(),
+        Ok(Claim::InProgress) => {
+            return message(
+                StatusCode::CONFLICT,
+                "A request with this Idempotency-Key is still being processed",
+            )
+        }
+        Ok(Claim::Mismatch) => {
+            return message(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used for a different request",
+            )
+        }
+        Err(e) => {
+            tracing::error!("Failed to claim idempotency key: {}", e);
+            return message(StatusCode::INTERNAL_SERVER_ERROR, "Failed to check Idempotency-Key");
+        }
+    };
+
+    let handled = async {
+        let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+        let (parts, body) = response.into_parts();
+        (to_bytes(body, usize::MAX).await, parts)
+    };
+    let (body, parts) = store.hold(&ctx.actor, &key, &token, handled).await;
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to buffer response for idempotency key: {}", e);
+            if let Err(e) = store.release(&ctx.actor, &key, &token).await {
+                tracing::error!("Failed to release idempotency key: {}", e);
+            }
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let result = if parts.status.is_server_error() {
+        store.release(&ctx.actor, &key, &token).await
+    } else if !store.can_store(&parts.extensions) {
+        tracing::warn!(
+            "Not storing a response carrying a secret for an Idempotency-Key, as ENCRYPTION_KEYS is not set"
+        );
+        store.release(&ctx.actor, &key, &token).await
+    } else {
+        let stored = StoredResponse::capture(parts.status, &parts.headers, &body);
+        store.complete(&ctx.actor, &key, &token, &stored).await
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => tracing::warn!(
+            "Idempotency key was taken over by another request; this response is not stored"
+        ),
+        Err(e) => tracing::error!("Failed to store response for idempotency key: {}", e),
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+fn message(status: StatusCode, message: &str) -> Response {
+    (status, Json(serde_json::json!({ "message": message }))).into_response()
+}
+
 pub async fn metrics_middleware(
     State(metrics): State<Arc<Metrics>>,
     request: Request<axum::body::Body>,
@@ -51,6 +245,7 @@ pub async fn metrics_middleware(
         "none"
     };
 
+    #[allow(unused_variables)]
     let error_class = if status >= 500 {
         "5xx"
     } else if status >= 400 {
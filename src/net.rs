@@ -0,0 +1,148 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// A list of IP networks, such as `TRUSTED_PROXIES` or `WEBHOOK_ALLOWED_NETWORKS`.
+#[derive(Debug, Clone, Default)]
+pub struct IpNetworks {
+    /// Networks as (address, prefix length)
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl IpNetworks {
+    /// Parses IP addresses and CIDR networks such as `10.0.0.0/8`, returning
+    /// the first entry that does not parse as the error.
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let mut networks = Vec::new();
+        for entry in entries {
+            let (address, prefix) = match entry.split_once('/') {
+                Some((address, prefix)) => (address, Some(prefix)),
+                None => (entry.as_str(), None),
+            };
+            let address: IpAddr = address.parse().map_err(|_| entry.clone())?;
+            let address = address.to_canonical();
+            let max = if address.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                Some(prefix) => prefix
+                    .parse()
+                    .ok()
+                    .filter(|prefix| *prefix <= max)
+                    .ok_or_else(|| entry.clone())?,
+                None => max,
+            };
+            networks.push((address, prefix));
+        }
+        Ok(Self { networks })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks
+            .iter()
+            .any(|(network, prefix)| match (network, ip) {
+                (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                    let mask = u32::MAX.checked_shl(32 - u32::from(*prefix)).unwrap_or(0);
+                    u32::from(*network) & mask == u32::from(ip) & mask
+                }
+                (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                    let mask = u128::MAX.checked_shl(128 - u32::from(*prefix)).unwrap_or(0);
+                    u128::from(*network) & mask == u128::from(ip) & mask
+                }
+                _ => false,
+            })
+    }
+}
+
+/// Whether `ip` is a globally routable address, rather than a loopback,
+/// private, link-local, shared, reserved or otherwise special-purpose one.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(a == 0
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || ip.is_broadcast()
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        // Documentation, 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+        // NAT64 and IPv4-compatible addresses would reach IPv4 hosts unchecked
+        || (first == 0x0064 && ip.segments()[1] == 0xff9b)
+        || first == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn tells_public_addresses_apart() {
+        for public in ["8.8.8.8", "1.1.1.1", "100.128.0.1", "2606:4700::1111"] {
+            assert!(is_public(ip(public)), "{}", public);
+        }
+        for internal in [
+            "0.0.0.0",
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "198.18.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "fd00::1",
+            "fe80::1",
+            "2001:db8::1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!is_public(ip(internal)), "{}", internal);
+        }
+    }
+
+    #[test]
+    fn parses_networks() {
+        let networks =
+            IpNetworks::parse(&["10.0.0.0/8".to_string(), "fd00::1".to_string()]).unwrap();
+        assert!(networks.contains(ip("10.200.0.1")));
+        assert!(networks.contains(ip("fd00::1")));
+        assert!(!networks.contains(ip("fd00::2")));
+        assert_eq!(
+            IpNetworks::parse(&["10.0.0.0/8".to_string(), "bad".to_string()]).unwrap_err(),
+            "bad"
+        );
+    }
+}
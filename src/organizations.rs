@@ -0,0 +1,403 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use std::collections::HashMap;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+use crate::auth::{Identity, Scope};
+use crate::db::Database;
+use crate::platform::string_enum;
+use crate::validation::{is_valid_slug, ValidationErrors};
+
+string_enum! {
+    /// Role of a member within an organization. Owners manage members and can
+    /// transfer the organization's projects away.
+    pub enum OrgRole {
+        Owner => "owner",
+        Member => "member",
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Organization {
+    #[schema(example = 1)]
+    pub id: i64,
+    #[schema(example = "Acme Inc.")]
+    pub name: String,
+    /// URL-friendly identifier (unique)
+    #[schema(example = "acme")]
+    pub slug: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct OrganizationMember {
+    pub organization_id: i64,
+    /// Principal (API key name) that is a member
+    #[schema(example = "ci-deployer")]
+    pub member: String,
+    pub role: OrgRole,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Request body for creating an organization
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateOrganization {
+    #[schema(example = "Acme Inc.")]
+    pub name: String,
+    /// 3-63 lowercase letters, digits or hyphens
+    #[schema(example = "acme")]
+    pub slug: String,
+    /// Principal to add as the first owner
+    #[schema(example = "acme-admin")]
+    pub owner: Option<String>,
+}
+
+/// A [`CreateOrganization`] that has passed validation.
+#[derive(Debug, Clone)]
+pub struct NewOrganization {
+    pub name: String,
+    pub slug: String,
+    pub owner: Option<String>,
+}
+
+impl CreateOrganization {
+    pub fn validate(self) -> Result<NewOrganization, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        let name = self.name.trim().to_string();
+        if name.is_empty() || name.len() > 255 {
+            errors.add("name", "must be between 1 and 255 characters");
+        }
+        if !is_valid_slug(&self.slug) {
+            errors.add(
+                "slug",
+                "must be 3-63 lowercase letters, digits or hyphens, not starting or ending with a hyphen",
+            );
+        }
+        let owner = self.owner.map(|o| o.trim().to_string());
+        if owner.as_deref().is_some_and(|o| !is_valid_member(o)) {
+            errors.add("owner", "must be between 1 and 100 characters");
+        }
+
+        errors.into_result(NewOrganization {
+            name,
+            slug: self.slug,
+            owner,
+        })
+    }
+}
+
+/// Request body for adding a member or changing their role
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct SetMemberRole {
+    #[schema(value_type = OrgRole)]
+    pub role: String,
+}
+
+impl SetMemberRole {
+    pub fn validate(self) -> Result<OrgRole, ValidationErrors> {
+        self.role.parse::<OrgRole>().map_err(|e| {
+            let mut errors = ValidationErrors::new();
+            errors.add("role", e);
+            errors
+        })
+    }
+}
+
+pub fn is_valid_member(member: &str) -> bool {
+    !member.is_empty() && member.len() <= 100
+}
+
+/// Which organizations' projects the caller may see and act on.
+#[derive(Debug, Clone)]
+pub enum OrgAccess {
+    /// Admin keys, and every caller while authentication is disabled
+    All,
+    /// The caller's memberships, by organization ID
+    Members(HashMap<i64, OrgRole>),
+}
+
+impl OrgAccess {
+    pub fn allows(&self, organization_id: i64) -> bool {
+        match self {
+            OrgAccess::All => true,
+            OrgAccess::Members(roles) => roles.contains_key(&organization_id),
+        }
+    }
+
+    pub fn is_owner(&self, organization_id: i64) -> bool {
+        match self {
+            OrgAccess::All => true,
+            OrgAccess::Members(roles) => roles.get(&organization_id) == Some(&OrgRole::Owner),
+        }
+    }
+
+    /// Organization IDs to restrict queries to, or `None` for no restriction.
+    pub fn organization_ids(&self) -> Option<Vec<i64>> {
+        match self {
+            OrgAccess::All => None,
+            OrgAccess::Members(roles) => Some(roles.keys().copied().collect()),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OrgAccess
+where
+    Arc<Database>: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let identity = match parts.extensions.get::<Identity>() {
+            Some(identity) if !identity.has_scope(Scope::Admin) => identity,
+            _ => return Ok(OrgAccess::All),
+        };
+
+        let db = Arc::<Database>::from_ref(state);
+        match db.organization_roles(&identity.principal).await {
+            Ok(roles) => Ok(OrgAccess::Members(roles)),
+            Err(e) => {
+                tracing::error!(
+                    "Failed to load organizations of {}: {}",
+                    identity.principal,
+                    e
+                );
+                Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to load organization memberships",
+                )
+                    .into_response())
+            }
+        }
+    }
+}
+
+/// Appends ` AND organization_id = ANY(...)` to a query that already has a WHERE
+/// clause, unless `access` is unrestricted.
+pub fn push_org_access(builder: &mut QueryBuilder<'_, Postgres>, access: &OrgAccess) {
+    if let Some(ids) = access.organization_ids() {
+        builder.push(" AND organization_id = ANY(").push_bind(ids).push(")");
+    }
+}
+
+/// Why a membership change was refused.
+#[derive(Debug)]
+pub enum MembershipError {
+    /// The change would leave the organization without an owner
+    LastOwner,
+    Database(sqlx::Error),
+}
+
+impl std::fmt::Display for MembershipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MembershipError::LastOwner => {
+                write!(f, "An organization must keep at least one owner")
+            }
+            MembershipError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl From<sqlx::Error> for MembershipError {
+    fn from(e: sqlx::Error) -> Self {
+        MembershipError::Database(e)
+    }
+}
+
+impl Database {
+    /// Creates an organization, with `input.owner` as its first owner if given.
+    pub async fn create_organization(
+        &self,
+        input: NewOrganization,
+    ) -> anyhow::Result<Organization> {
+        let mut tx = self.pool.begin().await?;
+
+        let organization = sqlx::query_as::<_, Organization>(
+            r#"
+            INSERT INTO organizations (name, slug)
+            VALUES ($1, $2)
+            RETURNING id, name, slug, created_at
+            "#,
+        )
+        .bind(&input.name)
+        .bind(&input.slug)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if let Some(owner) = &input.owner {
+            sqlx::query(
+                "INSERT INTO organization_members (organization_id, member, role) VALUES ($1, $2, $3)",
+            )
+            .bind(organization.id)
+            .bind(owner)
+            .bind(OrgRole::Owner)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(organization)
+    }
+
+    /// Lists the organizations visible with `access`, by name.
+    pub async fn list_organizations(&self, access: &OrgAccess) -> anyhow::Result<Vec<Organization>> {
+        let mut query: QueryBuilder<Postgres> = QueryBuilder::new(
+            "SELECT id, name, slug, created_at FROM organizations WHERE TRUE",
+        );
+        if let Some(ids) = access.organization_ids() {
+            query.push(" AND id = ANY(").push_bind(ids).push(")");
+        }
+        query.push(" ORDER BY name, id");
+
+        let organizations = query
+            .build_query_as::<Organization>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(organizations)
+    }
+
+    pub async fn get_organization(&self, id: i64) -> anyhow::Result<Option<Organization>> {
+        let organization = sqlx::query_as::<_, Organization>(
+            "SELECT id, name, slug, created_at FROM organizations WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(organization)
+    }
+
+    pub async fn list_organization_members(
+        &self,
+        organization_id: i64,
+    ) -> anyhow::Result<Vec<OrganizationMember>> {
+        let members = sqlx::query_as::<_, OrganizationMember>(
+            r#"
+            SELECT organization_id, member, role, created_at
+            FROM organization_members
+            WHERE organization_id = $1
+            ORDER BY member
+            "#,
+        )
+        .bind(organization_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(members)
+    }
+
+    /// Adds `member` to an organization or changes their role.
+    pub async fn set_organization_member(
+        &self,
+        organization_id: i64,
+        member: &str,
+        role: OrgRole,
+    ) -> Result<OrganizationMember, MembershipError> {
+        let mut tx = self.pool.begin().await?;
+        if role != OrgRole::Owner {
+            ensure_other_owner(&mut tx, organization_id, member).await?;
+        }
+
+        let membership = sqlx::query_as::<_, OrganizationMember>(
+            r#"
+            INSERT INTO organization_members (organization_id, member, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (organization_id, member) DO UPDATE SET role = EXCLUDED.role
+            RETURNING organization_id, member, role, created_at
+            "#,
+        )
+        .bind(organization_id)
+        .bind(member)
+        .bind(role)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(membership)
+    }
+
+    /// Removes `member` from an organization. Returns the removed membership, or
+    /// `None` if they were not a member.
+    pub async fn remove_organization_member(
+        &self,
+        organization_id: i64,
+        member: &str,
+    ) -> Result<Option<OrganizationMember>, MembershipError> {
+        let mut tx = self.pool.begin().await?;
+        ensure_other_owner(&mut tx, organization_id, member).await?;
+
+        let membership = sqlx::query_as::<_, OrganizationMember>(
+            r#"
+            DELETE FROM organization_members
+            WHERE organization_id = $1 AND member = $2
+            RETURNING organization_id, member, role, created_at
+            "#,
+        )
+        .bind(organization_id)
+        .bind(member)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(membership)
+    }
+
+    /// Organizations `principal` belongs to, with their role in each.
+    pub async fn organization_roles(
+        &self,
+        principal: &str,
+    ) -> anyhow::Result<HashMap<i64, OrgRole>> {
+        let rows: Vec<(i64, OrgRole)> = sqlx::query_as(
+            "SELECT organization_id, role FROM organization_members WHERE member = $1",
+        )
+        .bind(principal)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().collect())
+    }
+}
+
+/// Fails with [`MembershipError::LastOwner`] if `member` is the only owner of the
+/// organization. Locks the organization row so concurrent changes serialize.
+async fn ensure_other_owner(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    organization_id: i64,
+    member: &str,
+) -> Result<(), MembershipError> {
+    sqlx::query("SELECT id FROM organizations WHERE id = $1 FOR UPDATE")
+        .bind(organization_id)
+        .execute(&mut **tx)
+        .await?;
+
+    let (is_owner, other_owners): (bool, i64) = sqlx::query_as(
+        r#"
+        SELECT
+            COALESCE(BOOL_OR(member = $2), FALSE),
+            COUNT(*) FILTER (WHERE member <> $2)
+        FROM organization_members
+        WHERE organization_id = $1 AND role = 'owner'
+        "#,
+    )
+    .bind(organization_id)
+    .bind(member)
+    .fetch_one(&mut **tx)
+    .await?;
+
+    if is_owner && other_owners == 0 {
+        return Err(MembershipError::LastOwner);
+    }
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::db::Database;
+use crate::validation::{is_valid_slug, ValidationErrors};
+
+const PLAN_COLUMNS: &str = "name, display_name, max_db_size_bytes, max_connections, \
+     monthly_request_budget, retention_days, features, idle_suspend_after_hours, created_at, \
+     updated_at";
+
+/// A subscription plan and the limits of projects on it. Limits left empty are unlimited.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Plan {
+    /// Identifier referenced by projects' `plan`
+    #[schema(example = "pro")]
+    pub name: String,
+    #[schema(example = "Professional")]
+    pub display_name: String,
+    /// Largest database size allowed, in bytes
+    #[schema(example = 8589934592i64)]
+    pub max_db_size_bytes: Option<i64>,
+    /// Most concurrent connections allowed to the project database
+    #[schema(example = 200)]
+    pub max_connections: Option<i32>,
+    /// API requests included per calendar month
+    #[schema(example = 5000000)]
+    pub monthly_request_budget: Option<i64>,
+    /// Days of usage history kept for projects on the plan
+    #[schema(example = 7)]
+    pub retention_days: i32,
+    /// Features the plan is entitled to
+    #[schema(example = json!(["daily_backups", "custom_domains"]))]
+    pub features: Vec<String>,
+    /// Hours without activity after which projects on the plan are suspended automatically
+    #[schema(example = 24)]
+    pub idle_suspend_after_hours: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Limits and entitlements of a plan, as sent to create or replace one.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PlanSettings {
+    #[schema(example = "Professional")]
+    pub display_name: String,
+    /// Largest database size allowed, in bytes; omit for no limit
+    #[schema(example = 8589934592i64)]
+    pub max_db_size_bytes: Option<i64>,
+    /// Most concurrent connections allowed; omit for no limit
+    #[schema(example = 200)]
+    pub max_connections: Option<i32>,
+    /// API requests included per calendar month; omit for no limit
+    #[schema(example = 5000000)]
+    pub monthly_request_budget: Option<i64>,
+    /// Days of usage history kept (1-3650)
+    #[schema(example = 7)]
+    pub retention_days: i32,
+    /// Feature entitlements: lowercase letters, digits and underscores
+    #[serde(default)]
+    #[schema(example = json!(["daily_backups", "custom_domains"]))]
+    pub features: Vec<String>,
+    /// Suspend projects on the plan after this many hours without activity (1-8760);
+    /// omit to never suspend them automatically
+    #[schema(example = 24)]
+    pub idle_suspend_after_hours: Option<i32>,
+}
+
+impl PlanSettings {
+    /// Checks the settings and normalizes them (trimmed name, sorted unique features).
+    pub fn validate(self) -> Result<PlanSettings, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let settings = self.check(&mut errors);
+        errors.into_result(settings)
+    }
+
+    fn check(self, errors: &mut ValidationErrors) -> PlanSettings {
+        let display_name = self.display_name.trim().to_string();
+        if display_name.is_empty() || display_name.len() > 255 {
+            errors.add("display_name", "must be between 1 and 255 characters");
+        }
+        if self.max_db_size_bytes.is_some_and(|v| v <= 0) {
+            errors.add("max_db_size_bytes", "must be positive");
+        }
+        if self.max_connections.is_some_and(|v| v <= 0) {
+            errors.add("max_connections", "must be positive");
+        }
+        if self.monthly_request_budget.is_some_and(|v| v <= 0) {
+            errors.add("monthly_request_budget", "must be positive");
+        }
+        if !(1..=3650).contains(&self.retention_days) {
+            errors.add("retention_days", "must be between 1 and 3650");
+        }
+        if self
+            .idle_suspend_after_hours
+            .is_some_and(|h| !(1..=8760).contains(&h))
+        {
+            errors.add("idle_suspend_after_hours", "must be between 1 and 8760");
+        }
+
+        let mut features = self.features;
+        for feature in &features {
+            if !is_valid_feature(feature) {
+                errors.add(
+                    "features",
+                    format!("'{}' must be 1-50 lowercase letters, digits or underscores", feature),
+                );
+            }
+        }
+        features.sort();
+        features.dedup();
+
+        PlanSettings {
+            display_name,
+            features,
+            ..self
+        }
+    }
+}
+
+/// Request body for creating a plan
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreatePlan {
+    /// 3-63 lowercase letters, digits or hyphens
+    #[schema(example = "pro")]
+    pub name: String,
+    #[serde(flatten)]
+    pub settings: PlanSettings,
+}
+
+/// A [`CreatePlan`] that has passed validation.
+#[derive(Debug, Clone)]
+pub struct NewPlan {
+    pub name: String,
+    pub settings: PlanSettings,
+}
+
+impl CreatePlan {
+    pub fn validate(self) -> Result<NewPlan, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if !is_valid_slug(&self.name) {
+            errors.add(
+                "name",
+                "must be 3-63 lowercase letters, digits or hyphens, not starting or ending with a hyphen",
+            );
+        }
+        let settings = self.settings.check(&mut errors);
+
+        errors.into_result(NewPlan {
+            name: self.name,
+            settings,
+        })
+    }
+}
+
+fn is_valid_feature(feature: &str) -> bool {
+    !feature.is_empty()
+        && feature.len() <= 50
+        && feature
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+impl Database {
+    pub async fn list_plans(&self) -> anyhow::Result<Vec<Plan>> {
+        let plans = sqlx::query_as::<_, Plan>(&format!(
+            "SELECT {PLAN_COLUMNS} FROM plans ORDER BY name"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(plans)
+    }
+
+    pub async fn get_plan(&self, name: &str) -> anyhow::Result<Option<Plan>> {
+        let plan = sqlx::query_as::<_, Plan>(&format!(
+            "SELECT {PLAN_COLUMNS} FROM plans WHERE name = $1"
+        ))
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(plan)
+    }
+
+    pub async fn create_plan(&self, input: NewPlan) -> anyhow::Result<Plan> {
+        let settings = &input.settings;
+        let plan = sqlx::query_as::<_, Plan>(&format!(
+            r#"
+            INSERT INTO plans (name, display_name, max_db_size_bytes, max_connections,
+                               monthly_request_budget, retention_days, features,
+                               idle_suspend_after_hours)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING {PLAN_COLUMNS}
+            "#,
+        ))
+        .bind(&input.name)
+        .bind(&settings.display_name)
+        .bind(settings.max_db_size_bytes)
+        .bind(settings.max_connections)
+        .bind(settings.monthly_request_budget)
+        .bind(settings.retention_days)
+        .bind(&settings.features)
+        .bind(settings.idle_suspend_after_hours)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(plan)
+    }
+
+    /// Replaces the limits and entitlements of a plan. Returns `None` if it does not exist.
+    pub async fn update_plan(
+        &self,
+        name: &str,
+        settings: PlanSettings,
+    ) -> anyhow::Result<Option<Plan>> {
+        let plan = sqlx::query_as::<_, Plan>(&format!(
+            r#"
+            UPDATE plans
+            SET display_name = $2,
+                max_db_size_bytes = $3,
+                max_connections = $4,
+                monthly_request_budget = $5,
+                retention_days = $6,
+                features = $7,
+                idle_suspend_after_hours = $8,
+                updated_at = NOW()
+            WHERE name = $1
+            RETURNING {PLAN_COLUMNS}
+            "#,
+        ))
+        .bind(name)
+        .bind(&settings.display_name)
+        .bind(settings.max_db_size_bytes)
+        .bind(settings.max_connections)
+        .bind(settings.monthly_request_budget)
+        .bind(settings.retention_days)
+        .bind(&settings.features)
+        .bind(settings.idle_suspend_after_hours)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(plan)
+    }
+
+    /// Deletes a plan. Fails with a foreign key violation while projects still use it.
+    pub async fn delete_plan(&self, name: &str) -> anyhow::Result<Option<Plan>> {
+        let plan = sqlx::query_as::<_, Plan>(&format!(
+            "DELETE FROM plans WHERE name = $1 RETURNING {PLAN_COLUMNS}"
+        ))
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(plan)
+    }
+}
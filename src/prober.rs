@@ -0,0 +1,340 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgConnectOptions;
+use sqlx::{ConnectOptions, Connection, FromRow};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::ProbeConfig;
+use crate::db::Database;
+use crate::metrics::Metrics;
+
+/// One health check of a project: a Postgres connection to its `db_url` and an
+/// HTTP GET of its `api_base_url`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ProjectProbe {
+    pub id: i64,
+    pub project_id: i64,
+    /// Both checks passed
+    pub up: bool,
+    /// Connecting to the project database and running `SELECT 1` succeeded
+    pub db_up: bool,
+    /// Time to connect and run `SELECT 1`, in milliseconds
+    pub db_latency_ms: f64,
+    pub db_error: Option<String>,
+    /// The API answered with a non-5xx status
+    pub api_up: bool,
+    /// Time until the API's response headers arrived, in milliseconds
+    pub api_latency_ms: f64,
+    /// HTTP status returned by the API, if it answered
+    pub api_status: Option<i32>,
+    pub api_error: Option<String>,
+    pub probed_at: DateTime<Utc>,
+}
+
+/// Query parameters for listing probes.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProbeQuery {
+    /// Maximum number of probes to return, newest first (default 20, max 1000)
+    pub limit: Option<i64>,
+}
+
+impl ProbeQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(20).clamp(1, 1000)
+    }
+}
+
+/// A project to probe, with its connection string decrypted.
+struct ProbeTarget {
+    id: i64,
+    slug: String,
+    /// `None` if the stored value could not be decrypted
+    db_url: Option<String>,
+    api_base_url: String,
+}
+
+struct CheckResult {
+    up: bool,
+    latency: Duration,
+    error: Option<String>,
+}
+
+struct ProbeResult {
+    project_id: i64,
+    slug: String,
+    db: CheckResult,
+    api: CheckResult,
+    api_status: Option<u16>,
+}
+
+impl ProbeResult {
+    fn up(&self) -> bool {
+        self.db.up && self.api.up
+    }
+}
+
+/// Periodically probes every active project and records the outcome in
+/// `platform_project_probes` and the `platform_project_*` gauges.
+pub struct Prober {
+    db: Arc<Database>,
+    metrics: Arc<Metrics>,
+    http: reqwest::Client,
+    config: ProbeConfig,
+}
+
+impl Prober {
+    pub fn new(
+        db: Arc<Database>,
+        metrics: Arc<Metrics>,
+        config: ProbeConfig,
+    ) -> anyhow::Result<Arc<Self>> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .user_agent(concat!("TelemetryWatch/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+
+        Ok(Arc::new(Self {
+            db,
+            metrics,
+            http,
+            config,
+        }))
+    }
+
+    /// Probes all active projects every `interval_secs`. Meant to run under
+    /// [`crate::tasks::spawn_supervised`].
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.probe_all().await?;
+        }
+    }
+
+    async fn probe_all(self: &Arc<Self>) -> anyhow::Result<()> {
+        let targets = self.db.list_probe_targets().await?;
+
+        let semaphore = Arc::new(Semaphore::new(self.config.concurrency.max(1)));
+        let mut probes = JoinSet::new();
+        for target in targets {
+            let permit = semaphore.clone().acquire_owned().await?;
+            let prober = self.clone();
+            probes.spawn(async move {
+                let _permit = permit;
+                prober.probe(target).await
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(result) = probes.join_next().await {
+            results.push(result?);
+        }
+
+        self.export(&results);
+        self.db.record_probes(&results).await?;
+        self.db.prune_probes(self.config.retention_days).await?;
+
+        let down = results.iter().filter(|r| !r.up()).count();
+        tracing::debug!("Probed {} projects, {} down", results.len(), down);
+        Ok(())
+    }
+
+    async fn probe(&self, target: ProbeTarget) -> ProbeResult {
+        let timeout = Duration::from_secs(self.config.timeout_secs);
+        let db = match &target.db_url {
+            Some(url) => check_database(url, timeout).await,
+            None => CheckResult {
+                up: false,
+                latency: Duration::ZERO,
+                error: Some("stored db_url could not be decrypted".to_string()),
+            },
+        };
+
+        let start = Instant::now();
+        let (api, api_status) = match self.http.get(&target.api_base_url).send().await {
+            Ok(response) => {
+                let status = response.status();
+                let up = !status.is_server_error();
+                let check = CheckResult {
+                    up,
+                    latency: start.elapsed(),
+                    error: (!up).then(|| format!("HTTP {}", status)),
+                };
+                (check, Some(status.as_u16()))
+            }
+            Err(e) => {
+                let check = CheckResult {
+                    up: false,
+                    latency: start.elapsed(),
+                    error: Some(error_chain(&e.without_url())),
+                };
+                (check, None)
+            }
+        };
+
+        ProbeResult {
+            project_id: target.id,
+            slug: target.slug,
+            db,
+            api,
+            api_status,
+        }
+    }
+
+    /// Replaces the probe gauges with this round's results, so projects that are
+    /// no longer active stop being reported.
+    fn export(&self, results: &[ProbeResult]) {
+        self.metrics.platform_project_up.reset();
+        self.metrics.platform_project_probe_duration_seconds.reset();
+
+        for result in results {
+            self.metrics
+                .platform_project_up
+                .with_label_values(&[&result.slug])
+                .set(if result.up() { 1.0 } else { 0.0 });
+            for (check, outcome) in [("database", &result.db), ("api", &result.api)] {
+                self.metrics
+                    .platform_project_probe_duration_seconds
+                    .with_label_values(&[&result.slug, check])
+                    .set(outcome.latency.as_secs_f64());
+            }
+        }
+    }
+}
+
+async fn check_database(url: &str, timeout: Duration) -> CheckResult {
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, async {
+        let mut connection = PgConnectOptions::from_str(url)?
+            .application_name("telemetrywatch-prober")
+            .connect()
+            .await?;
+        sqlx::query("SELECT 1").execute(&mut connection).await?;
+        connection.close().await
+    })
+    .await;
+
+    let error = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("timed out after {}s", timeout.as_secs())),
+    };
+    CheckResult {
+        up: error.is_none(),
+        latency: start.elapsed(),
+        error,
+    }
+}
+
+/// `error` followed by its sources, e.g. "error sending request: connection refused".
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+impl Database {
+    /// Active projects with their decrypted connection strings.
+    async fn list_probe_targets(&self) -> anyhow::Result<Vec<ProbeTarget>> {
+        let rows: Vec<(i64, String, String, String)> = sqlx::query_as(
+            r#"
+            SELECT id, slug, db_url, api_base_url
+            FROM platform_projects
+            WHERE status = 'active'
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let targets = rows
+            .into_iter()
+            .map(|(id, slug, db_url, api_base_url)| {
+                let db_url = self
+                    .open_project_db_url(id, &db_url)
+                    .map_err(|e| tracing::warn!("Cannot decrypt db_url of project {}: {}", slug, e))
+                    .ok();
+                ProbeTarget {
+                    id,
+                    slug,
+                    db_url,
+                    api_base_url,
+                }
+            })
+            .collect();
+
+        Ok(targets)
+    }
+
+    async fn record_probes(&self, results: &[ProbeResult]) -> anyhow::Result<()> {
+        if results.is_empty() {
+            return Ok(());
+        }
+
+        let mut insert = sqlx::QueryBuilder::new(
+            "INSERT INTO platform_project_probes (project_id, up, db_up, db_latency_ms, db_error, \
+             api_up, api_latency_ms, api_status, api_error) ",
+        );
+        insert.push_values(results, |mut row, result| {
+            row.push_bind(result.project_id)
+                .push_bind(result.up())
+                .push_bind(result.db.up)
+                .push_bind(result.db.latency.as_secs_f64() * 1000.0)
+                .push_bind(result.db.error.as_deref())
+                .push_bind(result.api.up)
+                .push_bind(result.api.latency.as_secs_f64() * 1000.0)
+                .push_bind(result.api_status.map(i32::from))
+                .push_bind(result.api.error.as_deref());
+        });
+        insert.build().execute(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn prune_probes(&self, retention_days: i64) -> anyhow::Result<()> {
+        sqlx::query(
+            "DELETE FROM platform_project_probes WHERE probed_at < NOW() - make_interval(days => $1)",
+        )
+        .bind(retention_days as i32)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Most recent probes of a project, newest first.
+    pub async fn list_project_probes(
+        &self,
+        project_id: i64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<ProjectProbe>> {
+        let probes = sqlx::query_as::<_, ProjectProbe>(
+            r#"
+            SELECT id, project_id, up, db_up, db_latency_ms, db_error, api_up, api_latency_ms,
+                   api_status, api_error, probed_at
+            FROM platform_project_probes
+            WHERE project_id = $1
+            ORDER BY probed_at DESC, id DESC
+            LIMIT $2
+            "#,
+        )
+        .bind(project_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(probes)
+    }
+}
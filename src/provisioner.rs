@@ -0,0 +1,533 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use axum::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::ProvisionerConfig;
+use crate::platform::{PlatformProject, ProjectStatus};
+use crate::validation::is_postgres_url;
+
+/// Response bodies of the management endpoint are quoted in errors up to this many bytes.
+const MAX_QUOTED_BODY: usize = 512;
+
+/// PBKDF2 iterations of the SCRAM verifiers of provisioned roles, as Postgres uses by default.
+const SCRAM_ITERATIONS: u32 = 4096;
+
+/// What the provisioner is asked to do for a lifecycle transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProvisionAction {
+    Provision,
+    Suspend,
+    Resume,
+    Deprovision,
+}
+
+impl ProvisionAction {
+    /// The action that must succeed before a project moves from `from` to `to`,
+    /// if any.
+    pub fn for_transition(from: ProjectStatus, to: ProjectStatus) -> Option<Self> {
+        match (from, to) {
+            (ProjectStatus::Provisioning, ProjectStatus::Active) => {
+                Some(ProvisionAction::Provision)
+            }
+            (_, ProjectStatus::Suspended) => Some(ProvisionAction::Suspend),
+            (ProjectStatus::Suspended, ProjectStatus::Active) => Some(ProvisionAction::Resume),
+            (ProjectStatus::Deleting, ProjectStatus::Deleted) => Some(ProvisionAction::Deprovision),
+            _ => None,
+        }
+    }
+
+    /// The action undoing this one when its transition could not be recorded,
+    /// if the project is still `current` and that is the status `before` it had
+    /// when the action was taken. A project that has moved on meanwhile keeps
+    /// what its newer transition did, and deprovisioning cannot be undone.
+    pub fn undo(self, before: ProjectStatus, current: Option<ProjectStatus>) -> Option<Self> {
+        if current != Some(before) {
+            return None;
+        }
+        match self {
+            ProvisionAction::Provision => Some(ProvisionAction::Deprovision),
+            ProvisionAction::Suspend if before == ProjectStatus::Active => {
+                Some(ProvisionAction::Resume)
+            }
+            ProvisionAction::Resume => Some(ProvisionAction::Suspend),
+            ProvisionAction::Suspend | ProvisionAction::Deprovision => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ProvisionAction::Provision => "provision",
+            ProvisionAction::Suspend => "suspend",
+            ProvisionAction::Resume => "resume",
+            ProvisionAction::Deprovision => "deprovision",
+        }
+    }
+}
+
+impl std::fmt::Display for ProvisionAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Carries out lifecycle transitions on the infrastructure behind a project.
+/// A project only changes status once its provisioner has succeeded, so every
+/// method must be safe to retry.
+#[async_trait]
+pub trait ProjectProvisioner: Send + Sync {
+    /// Name of the backend, as set in `PROVISIONER`
+    fn name(&self) -> &'static str;
+
+    /// Creates the project's resources. Returns a connection string to store
+    /// as the project's `db_url` if the provisioner chose the database.
+    async fn provision(&self, project: &PlatformProject) -> anyhow::Result<Option<String>>;
+
+    async fn suspend(&self, project: &PlatformProject) -> anyhow::Result<()>;
+
+    async fn resume(&self, project: &PlatformProject) -> anyhow::Result<()>;
+
+    /// Removes the project's resources.
+    async fn deprovision(&self, project: &PlatformProject) -> anyhow::Result<()>;
+
+    /// Called once a transition this provisioner carried out has been recorded,
+    /// with the project as it is now. The transition stands whatever happens
+    /// here, so failures are the provisioner's to report.
+    async fn transitioned(&self, _project: &PlatformProject, _action: ProvisionAction) {}
+
+    /// Options to connect to the project's database as an administrator of its
+    /// server, if this provisioner has one. Unlike the project's own role, the
+    /// administrator can still log in once the project is suspended.
+    fn admin_connect_options(&self, _project: &PlatformProject) -> Option<PgConnectOptions> {
+        None
+    }
+}
+
+/// Builds the provisioner selected by `PROVISIONER`.
+pub fn from_config(config: &ProvisionerConfig) -> anyhow::Result<Arc<dyn ProjectProvisioner>> {
+    let provisioner: Arc<dyn ProjectProvisioner> = match config.backend.as_str() {
+        "noop" => Arc::new(NoopProvisioner),
+        "postgres" => {
+            let url = config.postgres_url.as_deref().ok_or_else(|| {
+                anyhow::anyhow!("PROVISIONER=postgres needs PROVISIONER_POSTGRES_URL")
+            })?;
+            Arc::new(PostgresProvisioner::new(url, config.timeout_secs)?)
+        }
+        "http" => {
+            let url = config
+                .http_url
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("PROVISIONER=http needs PROVISIONER_HTTP_URL"))?;
+            Arc::new(HttpProvisioner::new(
+                url,
+                config.http_token.clone(),
+                config.timeout_secs,
+            )?)
+        }
+        other => anyhow::bail!(
+            "unknown PROVISIONER '{}': expected noop, postgres or http",
+            other
+        ),
+    };
+    Ok(provisioner)
+}
+
+/// Changes nothing outside TelemetryWatch: projects are records of instances
+/// managed elsewhere.
+pub struct NoopProvisioner;
+
+#[async_trait]
+impl ProjectProvisioner for NoopProvisioner {
+    fn name(&self) -> &'static str {
+        "noop"
+    }
+
+    async fn provision(&self, _project: &PlatformProject) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn suspend(&self, _project: &PlatformProject) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn resume(&self, _project: &PlatformProject) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn deprovision(&self, _project: &PlatformProject) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Gives every project its own database and login role, `tw_project_<id>`, on
+/// a Postgres server TelemetryWatch administers. The project's `db_url` is
+/// replaced by one for the new role. Suspending a project takes away the
+/// role's LOGIN and ends its sessions.
+pub struct PostgresProvisioner {
+    admin: PgPool,
+    admin_options: PgConnectOptions,
+    /// Server connection string the project URLs are derived from
+    server_url: reqwest::Url,
+}
+
+impl PostgresProvisioner {
+    pub fn new(url: &str, timeout_secs: u64) -> anyhow::Result<Self> {
+        let server_url = reqwest::Url::parse(url)?;
+        let options =
+            PgConnectOptions::from_str(url)?.application_name("telemetrywatch-provisioner");
+        let admin = PgPoolOptions::new()
+            .max_connections(2)
+            .min_connections(0)
+            .acquire_timeout(Duration::from_secs(timeout_secs))
+            .connect_lazy_with(options.clone());
+        Ok(Self {
+            admin,
+            admin_options: options,
+            server_url,
+        })
+    }
+
+    fn name_of(project: &PlatformProject) -> String {
+        format!("tw_project_{}", project.id)
+    }
+
+    async fn role_exists(&self, name: &str) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = $1)")
+            .bind(name)
+            .fetch_one(&self.admin)
+            .await
+    }
+
+    async fn database_exists(&self, name: &str) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1)")
+            .bind(name)
+            .fetch_one(&self.admin)
+            .await
+    }
+
+    /// Ends the sessions of role `name`, or of those on database `name` if `database` is set.
+    async fn terminate_sessions(&self, name: &str, database: bool) -> Result<(), sqlx::Error> {
+        let column = if database { "datname" } else { "usename" };
+        sqlx::query(&format!(
+            "SELECT pg_terminate_backend(pid) FROM pg_stat_activity \
+             WHERE {column} = $1 AND pid <> pg_backend_pid()"
+        ))
+        .bind(name)
+        .execute(&self.admin)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProjectProvisioner for PostgresProvisioner {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn provision(&self, project: &PlatformProject) -> anyhow::Result<Option<String>> {
+        let name = Self::name_of(project);
+        let ident = quote_ident(&name);
+        let mut bytes = [0u8; 24];
+        OsRng.fill_bytes(&mut bytes);
+        let password = hex::encode(bytes);
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+
+        // DDL takes no bind parameters; the name and verifier are generated, not user
+        // input. Only the SCRAM verifier is sent, so that statement logging on the
+        // server (log_statement = ddl) does not record the password
+        let verb = if self.role_exists(&name).await? {
+            "ALTER"
+        } else {
+            "CREATE"
+        };
+        let verifier = scram_sha256_verifier(&password, &salt, SCRAM_ITERATIONS);
+        sqlx::query(&format!("{verb} ROLE {ident} LOGIN PASSWORD '{verifier}'"))
+            .execute(&self.admin)
+            .await?;
+        if !self.database_exists(&name).await? {
+            sqlx::query(&format!("CREATE DATABASE {ident} OWNER {ident}"))
+                .execute(&self.admin)
+                .await?;
+        }
+        sqlx::query(&format!("REVOKE ALL ON DATABASE {ident} FROM PUBLIC"))
+            .execute(&self.admin)
+            .await?;
+
+        let mut url = self.server_url.clone();
+        url.set_username(&name)
+            .and_then(|_| url.set_password(Some(&password)))
+            .map_err(|_| anyhow::anyhow!("PROVISIONER_POSTGRES_URL cannot carry credentials"))?;
+        url.set_path(&format!("/{}", name));
+        Ok(Some(url.to_string()))
+    }
+
+    async fn suspend(&self, project: &PlatformProject) -> anyhow::Result<()> {
+        let name = Self::name_of(project);
+        if self.role_exists(&name).await? {
+            sqlx::query(&format!("ALTER ROLE {} NOLOGIN", quote_ident(&name)))
+                .execute(&self.admin)
+                .await?;
+        }
+        self.terminate_sessions(&name, false).await?;
+        Ok(())
+    }
+
+    async fn resume(&self, project: &PlatformProject) -> anyhow::Result<()> {
+        let name = Self::name_of(project);
+        if !self.role_exists(&name).await? {
+            anyhow::bail!("role {} does not exist", name);
+        }
+        sqlx::query(&format!("ALTER ROLE {} LOGIN", quote_ident(&name)))
+            .execute(&self.admin)
+            .await?;
+        Ok(())
+    }
+
+    async fn deprovision(&self, project: &PlatformProject) -> anyhow::Result<()> {
+        let name = Self::name_of(project);
+        let ident = quote_ident(&name);
+        if self.database_exists(&name).await? {
+            sqlx::query(&format!("ALTER DATABASE {ident} ALLOW_CONNECTIONS false"))
+                .execute(&self.admin)
+                .await?;
+            self.terminate_sessions(&name, true).await?;
+            sqlx::query(&format!("DROP DATABASE IF EXISTS {ident}"))
+                .execute(&self.admin)
+                .await?;
+        }
+        self.terminate_sessions(&name, false).await?;
+        sqlx::query(&format!("DROP ROLE IF EXISTS {ident}"))
+            .execute(&self.admin)
+            .await?;
+        Ok(())
+    }
+
+    fn admin_connect_options(&self, project: &PlatformProject) -> Option<PgConnectOptions> {
+        Some(self.admin_options.clone().database(&Self::name_of(project)))
+    }
+}
+
+/// Quotes a Postgres identifier.
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// The SCRAM-SHA-256 verifier Postgres stores for `password` (RFC 5802, 7677):
+/// `SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>`. Postgres
+/// stores a password given in this form as is.
+fn scram_sha256_verifier(password: &str, salt: &[u8], iterations: u32) -> String {
+    let hmac = |key: &[u8], message: &[u8]| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(message);
+        mac.finalize().into_bytes()
+    };
+
+    // PBKDF2-HMAC-SHA256 with a single block, as the key is as long as the hash
+    let mut block = hmac(password.as_bytes(), &[salt, &1u32.to_be_bytes()].concat());
+    let mut salted_password = block;
+    for _ in 1..iterations {
+        block = hmac(password.as_bytes(), &block);
+        for (out, byte) in salted_password.iter_mut().zip(block.iter()) {
+            *out ^= byte;
+        }
+    }
+
+    let stored_key = Sha256::digest(hmac(&salted_password, b"Client Key"));
+    let server_key = hmac(&salted_password, b"Server Key");
+    format!(
+        "SCRAM-SHA-256${}:{}${}:{}",
+        iterations,
+        STANDARD.encode(salt),
+        STANDARD.encode(stored_key),
+        STANDARD.encode(server_key)
+    )
+}
+
+/// Asks a management service to carry out each action: a JSON
+/// `{"action": "...", "project": {...}}` is POSTed to `PROVISIONER_HTTP_URL`,
+/// with `PROVISIONER_HTTP_TOKEN` as bearer token if set. Any 2xx answer is
+/// success; for `provision` the answer may carry `{"db_url": "..."}`.
+pub struct HttpProvisioner {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProvisionResponse {
+    db_url: Option<String>,
+}
+
+impl HttpProvisioner {
+    pub fn new(url: &str, token: Option<String>, timeout_secs: u64) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(timeout_secs))
+            .user_agent(concat!(
+                "TelemetryWatch-Provisioner/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .build()?;
+        Ok(Self {
+            http,
+            url: url.to_string(),
+            token,
+        })
+    }
+
+    /// POSTs `action` and returns the response body.
+    async fn call(
+        &self,
+        action: ProvisionAction,
+        project: &PlatformProject,
+    ) -> anyhow::Result<String> {
+        let body = serde_json::to_vec(&serde_json::json!({
+            "action": action.as_str(),
+            "project": project,
+        }))?;
+        let mut request = self
+            .http
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if !status.is_success() {
+            let mut end = body.len().min(MAX_QUOTED_BODY);
+            while !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            anyhow::bail!("management endpoint answered {}: {}", status, &body[..end]);
+        }
+        Ok(body)
+    }
+}
+
+#[async_trait]
+impl ProjectProvisioner for HttpProvisioner {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    /// An empty answer leaves the `db_url` as it is; anything else must be a
+    /// JSON object, with a Postgres URL as `db_url` if it carries one.
+    async fn provision(&self, project: &PlatformProject) -> anyhow::Result<Option<String>> {
+        let body = self.call(ProvisionAction::Provision, project).await?;
+        if body.trim().is_empty() {
+            return Ok(None);
+        }
+        let response: ProvisionResponse = serde_json::from_str(&body)
+            .map_err(|e| anyhow::anyhow!("management endpoint answered invalid JSON: {}", e))?;
+        if let Some(url) = &response.db_url {
+            if !is_postgres_url(url) || PgConnectOptions::from_str(url).is_err() {
+                anyhow::bail!("management endpoint answered a db_url that is not a Postgres URL");
+            }
+        }
+        Ok(response.db_url)
+    }
+
+    async fn suspend(&self, project: &PlatformProject) -> anyhow::Result<()> {
+        self.call(ProvisionAction::Suspend, project).await?;
+        Ok(())
+    }
+
+    async fn resume(&self, project: &PlatformProject) -> anyhow::Result<()> {
+        self.call(ProvisionAction::Resume, project).await?;
+        Ok(())
+    }
+
+    async fn deprovision(&self, project: &PlatformProject) -> anyhow::Result<()> {
+        self.call(ProvisionAction::Deprovision, project).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_scram_verifier() {
+        let salt: Vec<u8> = (1..=16).collect();
+
+        assert_eq!(
+            scram_sha256_verifier("tw-test-password", &salt, 4096),
+            "SCRAM-SHA-256$4096:AQIDBAUGBwgJCgsMDQ4PEA==$\
+             tR+J+IV3pYXYOeBUeEMbS7UaPiWqMdtC3JeAgZkxXu8=:\
+             6h1GFBam3t2Cgygh2cPFYTiCC+v6vMhKhxrPtzbfqZo="
+        );
+    }
+
+    #[test]
+    fn quotes_identifiers() {
+        assert_eq!(quote_ident("tw_project_1"), "\"tw_project_1\"");
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+    }
+
+    #[test]
+    fn maps_transitions_to_actions() {
+        use ProjectStatus::*;
+
+        assert_eq!(
+            ProvisionAction::for_transition(Provisioning, Active),
+            Some(ProvisionAction::Provision)
+        );
+        assert_eq!(
+            ProvisionAction::for_transition(Active, Suspended),
+            Some(ProvisionAction::Suspend)
+        );
+        assert_eq!(
+            ProvisionAction::for_transition(Suspended, Active),
+            Some(ProvisionAction::Resume)
+        );
+        assert_eq!(
+            ProvisionAction::for_transition(Deleting, Deleted),
+            Some(ProvisionAction::Deprovision)
+        );
+        assert_eq!(ProvisionAction::for_transition(Active, Deleting), None);
+    }
+
+    #[test]
+    fn undoes_actions_of_unrecorded_transitions() {
+        use ProjectStatus::*;
+
+        // A suspend whose If-Match failed once the role was disabled
+        assert_eq!(
+            ProvisionAction::Suspend.undo(Active, Some(Active)),
+            Some(ProvisionAction::Resume)
+        );
+        assert_eq!(
+            ProvisionAction::Resume.undo(Suspended, Some(Suspended)),
+            Some(ProvisionAction::Suspend)
+        );
+        assert_eq!(
+            ProvisionAction::Provision.undo(Provisioning, Some(Provisioning)),
+            Some(ProvisionAction::Deprovision)
+        );
+        assert_eq!(ProvisionAction::Deprovision.undo(Deleting, Some(Deleting)), None);
+        assert_eq!(ProvisionAction::Suspend.undo(Failed, Some(Failed)), None);
+    }
+
+    #[test]
+    fn keeps_actions_of_projects_that_moved_on() {
+        use ProjectStatus::*;
+
+        // Suspended concurrently: the role must stay disabled
+        assert_eq!(ProvisionAction::Suspend.undo(Active, Some(Suspended)), None);
+        assert_eq!(ProvisionAction::Suspend.undo(Active, Some(Deleting)), None);
+        assert_eq!(ProvisionAction::Resume.undo(Suspended, Some(Active)), None);
+        assert_eq!(ProvisionAction::Provision.undo(Provisioning, None), None);
+    }
+}
@@ -0,0 +1,290 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::config::QuotaConfig;
+use crate::db::Database;
+use crate::metrics::Metrics;
+use crate::platform::string_enum;
+
+string_enum! {
+    /// A plan limit that is checked against collected usage: `db_size` against
+    /// `max_db_size_bytes`, `connections` against `max_connections` and
+    /// `requests` (API requests this calendar month) against `monthly_request_budget`.
+    pub enum QuotaResource {
+        DbSize => "db_size",
+        Connections => "connections",
+        Requests => "requests",
+    }
+}
+
+/// The latest evaluation of one of a project's quotas.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ProjectQuota {
+    pub project_id: i64,
+    pub resource: QuotaResource,
+    /// Plan whose limit was applied
+    #[schema(example = "pro")]
+    pub plan: String,
+    /// Usage at `measured_at`; for `requests`, the total of the month so far
+    pub used: i64,
+    /// The plan's limit
+    pub quota: i64,
+    /// `used / quota`; above 1 the quota is exceeded
+    #[schema(example = 0.42)]
+    pub utilization: f64,
+    pub exceeded: bool,
+    /// When the project started exceeding the quota, while it still does
+    pub exceeded_since: Option<DateTime<Utc>>,
+    /// When the usage was collected from the project database or, for `requests`, metered
+    pub measured_at: DateTime<Utc>,
+    pub evaluated_at: DateTime<Utc>,
+}
+
+/// Usage of a project next to the limits of its plan. The database size and
+/// connections are missing until they have been collected once.
+#[derive(Debug, FromRow)]
+struct UsageRow {
+    project_id: i64,
+    slug: String,
+    plan: String,
+    db_size_bytes: Option<i64>,
+    connections: Option<i32>,
+    collected_at: Option<DateTime<Utc>>,
+    monthly_requests: i64,
+    max_db_size_bytes: Option<i64>,
+    max_connections: Option<i32>,
+    monthly_request_budget: Option<i64>,
+}
+
+struct QuotaCheck {
+    project_id: i64,
+    slug: String,
+    plan: String,
+    resource: QuotaResource,
+    used: i64,
+    quota: i64,
+    measured_at: DateTime<Utc>,
+}
+
+impl QuotaCheck {
+    fn utilization(&self) -> f64 {
+        self.used as f64 / self.quota as f64
+    }
+
+    fn exceeded(&self) -> bool {
+        self.used > self.quota
+    }
+}
+
+/// Periodically compares the usage collected from project databases, and the
+/// API requests metered this month, with the limits of their plans, records the
+/// outcome in `platform_project_quotas` and exports
+/// `platform_project_quota_utilization`.
+pub struct QuotaEvaluator {
+    db: Arc<Database>,
+    metrics: Arc<Metrics>,
+    config: QuotaConfig,
+}
+
+impl QuotaEvaluator {
+    pub fn new(db: Arc<Database>, metrics: Arc<Metrics>, config: QuotaConfig) -> Arc<Self> {
+        Arc::new(Self {
+            db,
+            metrics,
+            config,
+        })
+    }
+
+    /// Evaluates quotas every `interval_secs`. Meant to run under
+    /// [`crate::tasks::spawn_supervised`].
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.evaluate().await?;
+        }
+    }
+
+    async fn evaluate(&self) -> anyhow::Result<()> {
+        let rows = self.db.list_quota_usage().await?;
+        let now = Utc::now();
+
+        let mut checks = Vec::new();
+        for row in rows {
+            let limits = [
+                (
+                    QuotaResource::DbSize,
+                    row.db_size_bytes.zip(row.collected_at),
+                    row.max_db_size_bytes,
+                ),
+                (
+                    QuotaResource::Connections,
+                    row.connections.map(i64::from).zip(row.collected_at),
+                    row.max_connections.map(i64::from),
+                ),
+                (
+                    QuotaResource::Requests,
+                    Some((row.monthly_requests, now)),
+                    row.monthly_request_budget,
+                ),
+            ];
+            for (resource, usage, quota) in limits {
+                let Some((used, measured_at)) = usage else { continue };
+                let Some(quota) = quota.filter(|q| *q > 0) else { continue };
+                checks.push(QuotaCheck {
+                    project_id: row.project_id,
+                    slug: row.slug.clone(),
+                    plan: row.plan.clone(),
+                    resource,
+                    used,
+                    quota,
+                    measured_at,
+                });
+            }
+        }
+
+        let newly_exceeded = self.db.record_quota_checks(&checks).await?;
+        for check in checks.iter().filter(|c| {
+            newly_exceeded.contains(&(c.project_id, c.resource))
+        }) {
+            tracing::warn!(
+                "Project {} exceeds the {} quota of plan {}: {} of {}",
+                check.slug,
+                check.resource,
+                check.plan,
+                check.used,
+                check.quota
+            );
+        }
+
+        self.export(&checks);
+        Ok(())
+    }
+
+    fn export(&self, checks: &[QuotaCheck]) {
+        self.metrics.platform_project_quota_utilization.reset();
+        for check in checks {
+            self.metrics
+                .platform_project_quota_utilization
+                .with_label_values(&[&check.slug, &check.plan, check.resource.as_str()])
+                .set(check.utilization());
+        }
+    }
+}
+
+impl Database {
+    /// Latest collected usage and API requests of the current UTC month of every
+    /// project, with its plan's limits.
+    async fn list_quota_usage(&self) -> anyhow::Result<Vec<UsageRow>> {
+        let rows = sqlx::query_as::<_, UsageRow>(
+            r#"
+            SELECT p.id AS project_id, p.slug, p.plan, u.db_size_bytes, u.connections,
+                   u.collected_at, COALESCE(r.requests, 0) AS monthly_requests,
+                   pl.max_db_size_bytes, pl.max_connections, pl.monthly_request_budget
+            FROM platform_projects p
+            JOIN plans pl ON pl.name = p.plan
+            LEFT JOIN platform_project_usage u ON u.project_id = p.id
+            LEFT JOIN (
+                SELECT project_id, SUM(api_requests)::BIGINT AS requests
+                FROM usage_records
+                WHERE granularity = 'day'
+                  AND period_start >= date_trunc('month', NOW() AT TIME ZONE 'UTC') AT TIME ZONE 'UTC'
+                GROUP BY project_id
+            ) r ON r.project_id = p.id
+            WHERE p.status <> 'deleted'
+            ORDER BY p.id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Stores this round's checks, dropping evaluations that no longer apply (a
+    /// limit was removed or the project deleted). Returns the (project, resource)
+    /// pairs that started exceeding their quota in this round.
+    async fn record_quota_checks(
+        &self,
+        checks: &[QuotaCheck],
+    ) -> anyhow::Result<Vec<(i64, QuotaResource)>> {
+        let mut tx = self.pool.begin().await?;
+
+        // NOW() is fixed for the transaction, so rows not written below keep an
+        // older evaluated_at, and exceeded_since equals evaluated_at only for
+        // quotas exceeded for the first time
+        let mut newly_exceeded = Vec::new();
+        if !checks.is_empty() {
+            let mut upsert = sqlx::QueryBuilder::new(
+                "INSERT INTO platform_project_quotas (project_id, resource, plan, used, quota, \
+                 utilization, exceeded, exceeded_since, measured_at, evaluated_at) ",
+            );
+            upsert.push_values(checks, |mut row, check| {
+                row.push_bind(check.project_id)
+                    .push_bind(check.resource)
+                    .push_bind(&check.plan)
+                    .push_bind(check.used)
+                    .push_bind(check.quota)
+                    .push_bind(check.utilization())
+                    .push_bind(check.exceeded())
+                    .push(if check.exceeded() { "NOW()" } else { "NULL" })
+                    .push_bind(check.measured_at)
+                    .push("NOW()");
+            });
+            upsert.push(
+                r#"
+                ON CONFLICT (project_id, resource) DO UPDATE SET
+                    plan = EXCLUDED.plan,
+                    used = EXCLUDED.used,
+                    quota = EXCLUDED.quota,
+                    utilization = EXCLUDED.utilization,
+                    exceeded = EXCLUDED.exceeded,
+                    exceeded_since = CASE
+                        WHEN EXCLUDED.exceeded
+                        THEN COALESCE(platform_project_quotas.exceeded_since, EXCLUDED.evaluated_at)
+                    END,
+                    measured_at = EXCLUDED.measured_at,
+                    evaluated_at = EXCLUDED.evaluated_at
+                RETURNING project_id, resource, exceeded_since = evaluated_at
+                "#,
+            );
+            let rows: Vec<(i64, QuotaResource, Option<bool>)> =
+                upsert.build_query_as().fetch_all(&mut *tx).await?;
+            newly_exceeded = rows
+                .into_iter()
+                .filter(|(_, _, newly)| *newly == Some(true))
+                .map(|(project_id, resource, _)| (project_id, resource))
+                .collect();
+        }
+
+        sqlx::query("DELETE FROM platform_project_quotas WHERE evaluated_at < NOW()")
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(newly_exceeded)
+    }
+
+    /// Latest quota evaluations of a project.
+    pub async fn list_project_quotas(&self, project_id: i64) -> anyhow::Result<Vec<ProjectQuota>> {
+        let quotas = sqlx::query_as::<_, ProjectQuota>(
+            r#"
+            SELECT project_id, resource, plan, used, quota, utilization, exceeded,
+                   exceeded_since, measured_at, evaluated_at
+            FROM platform_project_quotas
+            WHERE project_id = $1
+            ORDER BY resource
+            "#,
+        )
+        .bind(project_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(quotas)
+    }
+}
@@ -0,0 +1,632 @@
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::audit::NewAuditEntry;
+use crate::changes::{ChangeFeed, ChangeKind, ProjectChange};
+use crate::config::ScheduleConfig;
+use crate::db::Database;
+use crate::metrics::Metrics;
+use crate::platform::{string_enum, LifecycleError, ProjectStatus};
+use crate::provisioner::ProjectProvisioner;
+use crate::validation::ValidationErrors;
+
+/// Actor recorded in the event history of projects changed by a schedule.
+const SCHEDULER_ACTOR: &str = "scheduler";
+
+/// A claimed schedule is not due again for this long, in case the instance
+/// running it dies before recording the run. Schedules are claimed one at a
+/// time, so the lease only needs to cover a single transition.
+const LEASE: Duration = Duration::from_secs(300);
+
+const SCHEDULE_COLUMNS: &str = "id, project_id, action, cron, run_at, timezone, reason, enabled, \
+     next_run_at, last_run_at, last_outcome, last_error, created_by, created_at, updated_at";
+
+string_enum! {
+    /// Lifecycle action taken by a schedule
+    pub enum ScheduleAction {
+        Suspend => "suspend",
+        Resume => "resume",
+    }
+}
+
+string_enum! {
+    /// How the last run of a schedule went: `skipped` when the project was not
+    /// in a state the action applies to (e.g. already suspended), `missed` when
+    /// the run was overdue by more than `SCHEDULE_MISFIRE_GRACE_SECONDS`
+    pub enum ScheduleOutcome {
+        Succeeded => "succeeded",
+        Skipped => "skipped",
+        Missed => "missed",
+        Failed => "failed",
+    }
+}
+
+impl ScheduleAction {
+    /// Status the action moves a project to.
+    pub fn target_status(self) -> ProjectStatus {
+        match self {
+            ScheduleAction::Suspend => ProjectStatus::Suspended,
+            ScheduleAction::Resume => ProjectStatus::Active,
+        }
+    }
+}
+
+/// A recurring or one-off lifecycle action on a project.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ProjectSchedule {
+    #[schema(example = 1)]
+    pub id: i64,
+    pub project_id: i64,
+    pub action: ScheduleAction,
+    /// Cron expression (minute hour day-of-month month day-of-week) of a recurring schedule
+    #[schema(example = "0 22 * * 1-5")]
+    pub cron: Option<String>,
+    /// Time of a one-off schedule
+    pub run_at: Option<DateTime<Utc>>,
+    /// IANA time zone the cron expression is evaluated in
+    #[schema(example = "Europe/Berlin")]
+    pub timezone: String,
+    /// Recorded in the project's event history; defaults to `schedule <id>`
+    #[schema(example = "nightly shutdown of dev projects")]
+    pub reason: Option<String>,
+    pub enabled: bool,
+    /// When the schedule runs next; empty once a one-off schedule has run, or while disabled
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub last_outcome: Option<ScheduleOutcome>,
+    /// Why the last run did not succeed
+    pub last_error: Option<String>,
+    /// Actor that created the schedule
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request body for scheduling a lifecycle action; set either `cron` or `run_at`
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateProjectSchedule {
+    #[schema(example = "suspend")]
+    pub action: String,
+    /// Cron expression for a recurring schedule: minute, hour, day of month, month, day of week
+    #[schema(example = "0 22 * * *")]
+    pub cron: Option<String>,
+    /// Time of a one-off schedule, in the future
+    pub run_at: Option<DateTime<Utc>>,
+    /// IANA time zone of the cron expression (default `UTC`)
+    #[schema(example = "Europe/Berlin")]
+    pub timezone: Option<String>,
+    #[schema(example = "nightly shutdown of dev projects")]
+    pub reason: Option<String>,
+    /// Defaults to true
+    pub enabled: Option<bool>,
+}
+
+/// Request body for changing a schedule; omitted fields are kept. Setting `cron`
+/// makes a one-off schedule recurring, and setting `run_at` does the opposite.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct UpdateProjectSchedule {
+    #[schema(example = "resume")]
+    pub action: Option<String>,
+    #[schema(example = "0 7 * * 1-5")]
+    pub cron: Option<String>,
+    pub run_at: Option<DateTime<Utc>>,
+    #[schema(example = "UTC")]
+    pub timezone: Option<String>,
+    pub reason: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+/// A validated schedule, as written to the database.
+#[derive(Debug, Clone)]
+pub struct ScheduleSpec {
+    pub action: ScheduleAction,
+    pub cron: Option<String>,
+    pub run_at: Option<DateTime<Utc>>,
+    pub timezone: String,
+    pub reason: Option<String>,
+    pub enabled: bool,
+}
+
+impl CreateProjectSchedule {
+    pub fn validate(self) -> Result<ScheduleSpec, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        let action = parse_action(&self.action, &mut errors);
+        validate_run_at(self.run_at, &mut errors);
+        let spec = ScheduleSpec {
+            action: action.unwrap_or(ScheduleAction::Suspend),
+            cron: self.cron.map(|c| c.trim().to_string()),
+            run_at: self.run_at,
+            timezone: self
+                .timezone
+                .map(|t| t.trim().to_string())
+                .unwrap_or_else(|| "UTC".to_string()),
+            reason: self.reason.map(|r| r.trim().to_string()),
+            enabled: self.enabled.unwrap_or(true),
+        };
+        spec.validate(&mut errors);
+
+        errors.into_result(spec)
+    }
+}
+
+impl UpdateProjectSchedule {
+    /// `current` with the changes applied.
+    pub fn apply(self, current: &ProjectSchedule) -> Result<ScheduleSpec, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        let action = match &self.action {
+            Some(action) => parse_action(action, &mut errors),
+            None => Some(current.action),
+        };
+        validate_run_at(self.run_at, &mut errors);
+        let (cron, run_at) = match (self.cron, self.run_at) {
+            (None, None) => (current.cron.clone(), current.run_at),
+            (cron, run_at) => (cron.map(|c| c.trim().to_string()), run_at),
+        };
+        let spec = ScheduleSpec {
+            action: action.unwrap_or(current.action),
+            cron,
+            run_at,
+            timezone: self
+                .timezone
+                .map(|t| t.trim().to_string())
+                .unwrap_or_else(|| current.timezone.clone()),
+            reason: self
+                .reason
+                .map(|r| r.trim().to_string())
+                .or_else(|| current.reason.clone()),
+            enabled: self.enabled.unwrap_or(current.enabled),
+        };
+        spec.validate(&mut errors);
+
+        errors.into_result(spec)
+    }
+}
+
+impl ScheduleSpec {
+    fn validate(&self, errors: &mut ValidationErrors) {
+        match (&self.cron, self.run_at) {
+            (Some(_), Some(_)) => errors.add("cron", "cannot be combined with run_at"),
+            (None, None) => errors.add("cron", "either cron or run_at is required"),
+            (Some(cron), None) => {
+                if let Err(e) = parse_cron(cron) {
+                    errors.add("cron", e);
+                }
+            }
+            (None, Some(_)) => {}
+        }
+        if self.timezone.parse::<Tz>().is_err() {
+            errors.add(
+                "timezone",
+                "must be an IANA time zone such as UTC or Europe/Berlin",
+            );
+        }
+        if self.reason.as_ref().is_some_and(|r| r.len() > 255) {
+            errors.add("reason", "must be at most 255 characters");
+        }
+    }
+
+    /// First run after `after`, or `None` if the schedule is disabled or a
+    /// one-off schedule whose time has passed.
+    pub fn next_run_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if !self.enabled {
+            return None;
+        }
+        match (&self.cron, self.run_at) {
+            (Some(cron), _) => next_occurrence(cron, &self.timezone, after),
+            (None, Some(run_at)) => (run_at > after).then_some(run_at),
+            (None, None) => None,
+        }
+    }
+}
+
+fn parse_action(action: &str, errors: &mut ValidationErrors) -> Option<ScheduleAction> {
+    match action.parse::<ScheduleAction>() {
+        Ok(action) => Some(action),
+        Err(e) => {
+            errors.add("action", e);
+            None
+        }
+    }
+}
+
+fn validate_run_at(run_at: Option<DateTime<Utc>>, errors: &mut ValidationErrors) {
+    if run_at.is_some_and(|t| t <= Utc::now()) {
+        errors.add("run_at", "must be in the future");
+    }
+}
+
+/// Parses a standard five-field cron expression.
+fn parse_cron(cron: &str) -> Result<Cron, String> {
+    if cron.split_whitespace().count() != 5 {
+        return Err(
+            "must have five fields: minute, hour, day of month, month and day of week".to_string(),
+        );
+    }
+    Cron::new(cron)
+        .parse()
+        .map_err(|e| format!("is not a valid cron expression: {}", e))
+}
+
+/// First time after `after` matching `cron` in `timezone`.
+fn next_occurrence(cron: &str, timezone: &str, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let cron = parse_cron(cron).ok()?;
+    let timezone: Tz = timezone.parse().ok()?;
+    cron.find_next_occurrence(&after.with_timezone(&timezone), false)
+        .ok()
+        .map(|next| next.with_timezone(&Utc))
+}
+
+/// A schedule claimed for a run, with the time it was due.
+#[derive(Debug, FromRow)]
+struct DueSchedule {
+    #[sqlx(flatten)]
+    schedule: ProjectSchedule,
+    due_at: DateTime<Utc>,
+}
+
+/// Runs due project schedules. Schedules are claimed with `SKIP LOCKED`, so any
+/// number of replicas can run the scheduler without running a schedule twice.
+pub struct Scheduler {
+    db: Arc<Database>,
+    metrics: Arc<Metrics>,
+    changes: Arc<ChangeFeed>,
+    provisioner: Arc<dyn ProjectProvisioner>,
+    config: ScheduleConfig,
+}
+
+impl Scheduler {
+    pub fn new(
+        db: Arc<Database>,
+        metrics: Arc<Metrics>,
+        changes: Arc<ChangeFeed>,
+        provisioner: Arc<dyn ProjectProvisioner>,
+        config: ScheduleConfig,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            db,
+            metrics,
+            changes,
+            provisioner,
+            config,
+        })
+    }
+
+    /// Runs due schedules every `poll_interval_secs`. Meant to run under
+    /// [`crate::tasks::spawn_supervised`].
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.poll_interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            while self.run_due().await? {}
+        }
+    }
+
+    /// Runs the schedule that has been due the longest, if any. Returns whether
+    /// there was one.
+    async fn run_due(&self) -> anyhow::Result<bool> {
+        match self.db.claim_project_schedule(LEASE).await? {
+            Some(due) => {
+                self.execute(due).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn execute(&self, due: DueSchedule) -> anyhow::Result<()> {
+        let schedule = &due.schedule;
+        let now = Utc::now();
+        let late = (now - due.due_at).num_seconds();
+
+        let (outcome, error) = if late > self.config.misfire_grace_secs {
+            (
+                ScheduleOutcome::Missed,
+                Some(format!("the run was due {}s earlier", late)),
+            )
+        } else {
+            let reason = schedule
+                .reason
+                .clone()
+                .unwrap_or_else(|| format!("schedule {}", schedule.id));
+            let before = self.db.get_platform_project(schedule.project_id).await?;
+            let result = self
+                .db
+                .update_platform_project_status(
+                    self.provisioner.as_ref(),
+                    schedule.project_id,
+                    schedule.action.target_status(),
+                    SCHEDULER_ACTOR,
+                    Some(&reason),
+                    None,
+                )
+                .await;
+            let entry = NewAuditEntry::background(
+                SCHEDULER_ACTOR,
+                format!("schedule-{}", schedule.id),
+                &format!("project.{}", schedule.action),
+                "platform_project",
+                Some(schedule.project_id.to_string()),
+            )
+            .before(before.as_ref())
+            .after(result.as_ref().ok())
+            .status(result.as_ref().map_or_else(LifecycleError::status_code, |_| 200))
+            .error(result.as_ref().err());
+            self.db.record_audit(entry, &self.metrics).await;
+            match result {
+                Ok(project) => {
+                    let mut change =
+                        ProjectChange::new(ChangeKind::StatusChanged, &project, SCHEDULER_ACTOR);
+                    if let Some(before) = before {
+                        change = change.previous_status(before.status);
+                    }
+                    self.changes.publish(change);
+                    (ScheduleOutcome::Succeeded, None)
+                }
+                Err(e @ (LifecycleError::InvalidTransition { .. } | LifecycleError::NotFound)) => {
+                    (ScheduleOutcome::Skipped, Some(e.to_string()))
+                }
+                Err(e) => {
+                    tracing::error!("Schedule {} failed: {}", schedule.id, e);
+                    (ScheduleOutcome::Failed, Some(e.to_string()))
+                }
+            }
+        };
+
+        self.metrics
+            .project_schedule_runs_total
+            .with_label_values(&[schedule.action.as_str(), outcome.as_str()])
+            .inc();
+        tracing::info!(
+            "Schedule {} ({} project {}): {}",
+            schedule.id,
+            schedule.action,
+            schedule.project_id,
+            outcome
+        );
+
+        let next_run_at = ScheduleSpec::from(schedule).next_run_after(now);
+        let recorded = self
+            .db
+            .record_schedule_run(
+                schedule.id,
+                schedule.next_run_at,
+                outcome,
+                error.as_deref(),
+                next_run_at,
+            )
+            .await?;
+        if !recorded {
+            tracing::warn!(
+                "Schedule {} was changed or claimed again while running; its run is not recorded",
+                schedule.id
+            );
+        }
+        Ok(())
+    }
+}
+
+impl From<&ProjectSchedule> for ScheduleSpec {
+    fn from(schedule: &ProjectSchedule) -> Self {
+        Self {
+            action: schedule.action,
+            cron: schedule.cron.clone(),
+            run_at: schedule.run_at,
+            timezone: schedule.timezone.clone(),
+            reason: schedule.reason.clone(),
+            enabled: schedule.enabled,
+        }
+    }
+}
+
+impl Database {
+    pub async fn create_project_schedule(
+        &self,
+        project_id: i64,
+        spec: &ScheduleSpec,
+        created_by: &str,
+    ) -> anyhow::Result<ProjectSchedule> {
+        let schedule = sqlx::query_as::<_, ProjectSchedule>(&format!(
+            r#"
+            INSERT INTO project_schedules
+                (project_id, action, cron, run_at, timezone, reason, enabled, next_run_at, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING {SCHEDULE_COLUMNS}
+            "#,
+        ))
+        .bind(project_id)
+        .bind(spec.action)
+        .bind(&spec.cron)
+        .bind(spec.run_at)
+        .bind(&spec.timezone)
+        .bind(&spec.reason)
+        .bind(spec.enabled)
+        .bind(spec.next_run_after(Utc::now()))
+        .bind(created_by)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(schedule)
+    }
+
+    pub async fn list_project_schedules(
+        &self,
+        project_id: i64,
+    ) -> anyhow::Result<Vec<ProjectSchedule>> {
+        let schedules = sqlx::query_as::<_, ProjectSchedule>(&format!(
+            "SELECT {SCHEDULE_COLUMNS} FROM project_schedules WHERE project_id = $1 ORDER BY id"
+        ))
+        .bind(project_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(schedules)
+    }
+
+    pub async fn get_project_schedule(
+        &self,
+        project_id: i64,
+        id: i64,
+    ) -> anyhow::Result<Option<ProjectSchedule>> {
+        let schedule = sqlx::query_as::<_, ProjectSchedule>(&format!(
+            "SELECT {SCHEDULE_COLUMNS} FROM project_schedules WHERE id = $1 AND project_id = $2"
+        ))
+        .bind(id)
+        .bind(project_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(schedule)
+    }
+
+    /// Replaces a schedule's settings and recomputes its next run. Returns `None`
+    /// if the schedule does not belong to the project.
+    pub async fn update_project_schedule(
+        &self,
+        project_id: i64,
+        id: i64,
+        spec: &ScheduleSpec,
+    ) -> anyhow::Result<Option<ProjectSchedule>> {
+        let schedule = sqlx::query_as::<_, ProjectSchedule>(&format!(
+            r#"
+            UPDATE project_schedules
+            SET action = $3, cron = $4, run_at = $5, timezone = $6, reason = $7, enabled = $8,
+                next_run_at = $9, updated_at = NOW()
+            WHERE id = $1 AND project_id = $2
+            RETURNING {SCHEDULE_COLUMNS}
+            "#,
+        ))
+        .bind(id)
+        .bind(project_id)
+        .bind(spec.action)
+        .bind(&spec.cron)
+        .bind(spec.run_at)
+        .bind(&spec.timezone)
+        .bind(&spec.reason)
+        .bind(spec.enabled)
+        .bind(spec.next_run_after(Utc::now()))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(schedule)
+    }
+
+    /// Returns `false` if the schedule does not belong to the project.
+    pub async fn delete_project_schedule(&self, project_id: i64, id: i64) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM project_schedules WHERE id = $1 AND project_id = $2")
+            .bind(id)
+            .bind(project_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Takes the longest-due schedule of a project that is not deleted, pushing
+    /// its next run back by `lease` so that no other instance picks it up
+    /// meanwhile. The returned schedule's `next_run_at` is the end of the lease.
+    async fn claim_project_schedule(&self, lease: Duration) -> anyhow::Result<Option<DueSchedule>> {
+        let due = sqlx::query_as::<_, DueSchedule>(
+            r#"
+            WITH due AS (
+                SELECT ps.id, ps.next_run_at
+                FROM project_schedules ps
+                JOIN platform_projects p ON p.id = ps.project_id
+                WHERE ps.enabled AND ps.next_run_at <= NOW() AND p.status <> 'deleted'
+                ORDER BY ps.next_run_at
+                LIMIT 1
+                FOR UPDATE OF ps SKIP LOCKED
+            )
+            UPDATE project_schedules s
+            SET next_run_at = NOW() + make_interval(secs => $1)
+            FROM due
+            WHERE s.id = due.id
+            RETURNING s.*, due.next_run_at AS due_at
+            "#,
+        )
+        .bind(lease.as_secs_f64())
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(due)
+    }
+
+    /// Records a run of a schedule claimed with lease `claimed_until`. Returns
+    /// `false` without recording if the schedule no longer holds that lease,
+    /// because it was edited or claimed again meanwhile.
+    async fn record_schedule_run(
+        &self,
+        id: i64,
+        claimed_until: Option<DateTime<Utc>>,
+        outcome: ScheduleOutcome,
+        error: Option<&str>,
+        next_run_at: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE project_schedules
+            SET last_run_at = NOW(), last_outcome = $3, last_error = $4, next_run_at = $5
+            WHERE id = $1 AND next_run_at IS NOT DISTINCT FROM $2
+            "#,
+        )
+        .bind(id)
+        .bind(claimed_until)
+        .bind(outcome)
+        .bind(error)
+        .bind(next_run_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_five_field_cron_expressions() {
+        assert!(parse_cron("0 2 * * 1-5").is_ok());
+        assert!(parse_cron("*/15 8-18 1,15 * *").is_ok());
+        assert!(parse_cron("0 0 2 * * *").is_err());
+        assert!(parse_cron("0 2 * *").is_err());
+        assert!(parse_cron("61 * * * *").is_err());
+        assert!(parse_cron("0 2 * * funday").is_err());
+    }
+
+    #[test]
+    fn finds_next_occurrence_in_time_zone() {
+        // 02:00 in Berlin is 01:00 UTC in winter and 00:00 UTC in summer
+        assert_eq!(
+            next_occurrence("0 2 * * *", "Europe/Berlin", utc("2026-01-09T12:00:00Z")),
+            Some(utc("2026-01-10T01:00:00Z"))
+        );
+        assert_eq!(
+            next_occurrence("0 2 * * *", "Europe/Berlin", utc("2026-07-09T12:00:00Z")),
+            Some(utc("2026-07-10T00:00:00Z"))
+        );
+        // Strictly after: a run at the exact time is not due again
+        assert_eq!(
+            next_occurrence("0 2 * * *", "UTC", utc("2026-07-10T02:00:00Z")),
+            Some(utc("2026-07-11T02:00:00Z"))
+        );
+        assert_eq!(
+            next_occurrence("0 2 * * *", "Mars/Olympus", utc("2026-07-10T00:00:00Z")),
+            None
+        );
+    }
+}
@@ -0,0 +1,35 @@
+use std::future::Future;
+use std::time::Duration;
+
+/// Longest pause between restarts of a task that keeps failing.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// Runs a long-lived background task and restarts it whenever it returns or
+/// panics, backing off exponentially while it keeps failing quickly.
+pub fn spawn_supervised<F, Fut>(name: &'static str, task: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut delay = Duration::from_secs(1);
+        loop {
+            let started = tokio::time::Instant::now();
+            match tokio::spawn(task()).await {
+                Ok(Ok(())) => tracing::warn!("Background task {} exited; restarting", name),
+                Ok(Err(e)) => tracing::error!("Background task {} failed: {}; restarting", name, e),
+                Err(e) if e.is_panic() => {
+                    tracing::error!("Background task {} panicked; restarting", name)
+                }
+                Err(e) => tracing::error!("Background task {} was cancelled: {}", name, e),
+            }
+
+            // A task that ran for a while before failing starts over with a short delay
+            if started.elapsed() > MAX_RESTART_DELAY {
+                delay = Duration::from_secs(1);
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RESTART_DELAY);
+        }
+    });
+}
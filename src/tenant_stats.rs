@@ -0,0 +1,408 @@
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::config::TenantStatsConfig;
+use crate::db::Database;
+use crate::metrics::Metrics;
+
+/// Counters of the tenant database from `pg_stat_database`.
+#[derive(Debug, FromRow)]
+struct DatabaseCounters {
+    xact_commit: i64,
+    xact_rollback: i64,
+    blks_hit: i64,
+    blks_read: i64,
+    deadlocks: i64,
+}
+
+/// One round of statistics from a tenant database.
+#[derive(Debug)]
+struct TenantStats {
+    counters: DatabaseCounters,
+    /// Backends connected to the database, by `pg_stat_activity.state`
+    connections: Vec<(String, i64)>,
+    size_bytes: i64,
+    longest_transaction_seconds: f64,
+    /// Scans and row changes of the project's tables since the statistics were reset
+    table_activity: i64,
+    /// Client backends running a query or in a transaction, other than TelemetryWatch's own
+    active_connections: i64,
+}
+
+struct CollectResult {
+    project_id: i64,
+    slug: String,
+    stats: Option<TenantStats>,
+}
+
+/// A connection pool to one tenant database, kept across rounds.
+struct TenantPool {
+    /// Decrypted URL the pool was built from, to notice when it changes
+    db_url: String,
+    pool: PgPool,
+}
+
+/// Periodically reads activity and size statistics from every active project's
+/// database and exports them as `platform_project_db_*` gauges.
+///
+/// Each tenant gets its own small pool and every collection is bounded by a
+/// timeout, so a slow or unreachable tenant only delays its own numbers.
+pub struct TenantStatsCollector {
+    db: Arc<Database>,
+    metrics: Arc<Metrics>,
+    config: TenantStatsConfig,
+    pools: Mutex<HashMap<i64, TenantPool>>,
+}
+
+impl TenantStatsCollector {
+    pub fn new(db: Arc<Database>, metrics: Arc<Metrics>, config: TenantStatsConfig) -> Arc<Self> {
+        Arc::new(Self {
+            db,
+            metrics,
+            config,
+            pools: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Collects statistics every `interval_secs`. Meant to run under
+    /// [`crate::tasks::spawn_supervised`].
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.collect_all().await?;
+        }
+    }
+
+    async fn collect_all(self: &Arc<Self>) -> anyhow::Result<()> {
+        let tenants = self.db.list_tenant_databases().await?;
+        let pools = self.sync_pools(&tenants);
+
+        let semaphore = Arc::new(Semaphore::new(self.config.concurrency.max(1)));
+        let mut collections = JoinSet::new();
+        for (id, slug, _) in tenants {
+            let Some(pool) = pools.get(&id).cloned() else {
+                collections.spawn(async move {
+                    CollectResult {
+                        project_id: id,
+                        slug,
+                        stats: None,
+                    }
+                });
+                continue;
+            };
+            let permit = semaphore.clone().acquire_owned().await?;
+            let timeout = Duration::from_secs(self.config.timeout_secs);
+            collections.spawn(async move {
+                let _permit = permit;
+                let stats = match tokio::time::timeout(timeout, collect(&pool)).await {
+                    Ok(Ok(stats)) => Some(stats),
+                    Ok(Err(e)) => {
+                        tracing::debug!("Collecting stats of project {} failed: {}", slug, e);
+                        None
+                    }
+                    Err(_) => {
+                        tracing::debug!(
+                            "Collecting stats of project {} timed out after {}s",
+                            slug,
+                            timeout.as_secs()
+                        );
+                        None
+                    }
+                };
+                CollectResult {
+                    project_id: id,
+                    slug,
+                    stats,
+                }
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(result) = collections.join_next().await {
+            results.push(result?);
+        }
+
+        self.export(&results);
+        self.db.record_tenant_usage(&results).await?;
+        let failed = results.iter().filter(|r| r.stats.is_none()).count();
+        tracing::debug!(
+            "Collected stats of {} project databases, {} failed",
+            results.len(),
+            failed
+        );
+        Ok(())
+    }
+
+    /// Opens pools for new tenants, rebuilds those whose URL changed and closes
+    /// those that are no longer active. Returns the pool of every tenant with a
+    /// usable URL.
+    fn sync_pools(&self, tenants: &[(i64, String, Option<String>)]) -> HashMap<i64, PgPool> {
+        let mut pools = self.pools.lock().unwrap();
+        let mut retired = Vec::new();
+
+        let active: HashMap<i64, &str> = tenants
+            .iter()
+            .filter_map(|(id, _, url)| url.as_deref().map(|url| (*id, url)))
+            .collect();
+        pools.retain(|id, tenant| {
+            let keep = active.get(id) == Some(&tenant.db_url.as_str());
+            if !keep {
+                retired.push(tenant.pool.clone());
+            }
+            keep
+        });
+
+        for (id, slug, _) in tenants {
+            let Some(url) = active.get(id) else { continue };
+            if pools.contains_key(id) {
+                continue;
+            }
+            match self.connect_lazy(url) {
+                Ok(pool) => {
+                    pools.insert(
+                        *id,
+                        TenantPool {
+                            db_url: url.to_string(),
+                            pool,
+                        },
+                    );
+                }
+                Err(e) => tracing::warn!("Invalid db_url for project {}: {}", slug, e),
+            }
+        }
+
+        for pool in retired {
+            tokio::spawn(async move { pool.close().await });
+        }
+
+        pools
+            .iter()
+            .map(|(id, tenant)| (*id, tenant.pool.clone()))
+            .collect()
+    }
+
+    fn connect_lazy(&self, url: &str) -> anyhow::Result<PgPool> {
+        let options = PgConnectOptions::from_str(url)?.application_name("telemetrywatch-stats");
+        let pool = PgPoolOptions::new()
+            .max_connections(self.config.pool_size.max(1))
+            .min_connections(0)
+            .acquire_timeout(Duration::from_secs(self.config.timeout_secs))
+            // Keep the connection between rounds, but don't hold it forever
+            .idle_timeout(Duration::from_secs(self.config.interval_secs * 3))
+            .connect_lazy_with(options);
+        Ok(pool)
+    }
+
+    /// Replaces the tenant gauges with this round's results, so projects that are
+    /// gone or unreachable stop reporting stale numbers.
+    fn export(&self, results: &[CollectResult]) {
+        let m = &self.metrics;
+        m.platform_project_db_stats_up.reset();
+        m.platform_project_db_transactions.reset();
+        m.platform_project_db_blocks.reset();
+        m.platform_project_db_deadlocks.reset();
+        m.platform_project_db_connections.reset();
+        m.platform_project_db_size_bytes.reset();
+        m.platform_project_db_longest_transaction_seconds.reset();
+
+        for result in results {
+            let slug = result.slug.as_str();
+            let Some(stats) = &result.stats else {
+                m.platform_project_db_stats_up
+                    .with_label_values(&[slug])
+                    .set(0.0);
+                continue;
+            };
+            m.platform_project_db_stats_up
+                .with_label_values(&[slug])
+                .set(1.0);
+
+            let counters = &stats.counters;
+            m.platform_project_db_transactions
+                .with_label_values(&[slug, "commit"])
+                .set(counters.xact_commit as f64);
+            m.platform_project_db_transactions
+                .with_label_values(&[slug, "rollback"])
+                .set(counters.xact_rollback as f64);
+            m.platform_project_db_blocks
+                .with_label_values(&[slug, "hit"])
+                .set(counters.blks_hit as f64);
+            m.platform_project_db_blocks
+                .with_label_values(&[slug, "read"])
+                .set(counters.blks_read as f64);
+            m.platform_project_db_deadlocks
+                .with_label_values(&[slug])
+                .set(counters.deadlocks as f64);
+
+            for (state, count) in &stats.connections {
+                m.platform_project_db_connections
+                    .with_label_values(&[slug, state])
+                    .set(*count as f64);
+            }
+            m.platform_project_db_size_bytes
+                .with_label_values(&[slug])
+                .set(stats.size_bytes as f64);
+            m.platform_project_db_longest_transaction_seconds
+                .with_label_values(&[slug])
+                .set(stats.longest_transaction_seconds);
+        }
+    }
+}
+
+impl TenantStats {
+    /// Backends connected to the database, in any state.
+    fn total_connections(&self) -> i64 {
+        self.connections.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// Reads one round of statistics over a single connection from `pool`.
+async fn collect(pool: &PgPool) -> anyhow::Result<TenantStats> {
+    let mut conn = pool.acquire().await?;
+
+    let counters = sqlx::query_as::<_, DatabaseCounters>(
+        r#"
+        SELECT xact_commit, xact_rollback, blks_hit, blks_read, deadlocks
+        FROM pg_stat_database
+        WHERE datname = current_database()
+        "#,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    // Our own backend is left out so the collector doesn't count itself
+    let connections: Vec<(String, i64)> = sqlx::query_as(
+        r#"
+        SELECT COALESCE(state, 'unknown'), COUNT(*)
+        FROM pg_stat_activity
+        WHERE datname = current_database() AND pid <> pg_backend_pid()
+        GROUP BY 1
+        "#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let (size_bytes, longest_transaction_seconds): (i64, f64) = sqlx::query_as(
+        r#"
+        SELECT
+            pg_database_size(current_database()),
+            COALESCE((
+                SELECT EXTRACT(EPOCH FROM MAX(NOW() - xact_start))::FLOAT8
+                FROM pg_stat_activity
+                WHERE datname = current_database()
+                  AND xact_start IS NOT NULL
+                  AND pid <> pg_backend_pid()
+            ), 0)
+        "#,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    // Activity for idle detection. Transaction counters are no use here, since
+    // our own probes and these queries commit transactions too; they never touch
+    // the project's tables though.
+    let (table_activity, active_connections): (i64, i64) = sqlx::query_as(
+        r#"
+        SELECT
+            COALESCE((
+                SELECT SUM(seq_scan + COALESCE(idx_scan, 0) + n_tup_ins + n_tup_upd + n_tup_del)
+                FROM pg_stat_user_tables
+            ), 0)::BIGINT,
+            (
+                SELECT COUNT(*)
+                FROM pg_stat_activity
+                WHERE datname = current_database()
+                  AND pid <> pg_backend_pid()
+                  AND backend_type = 'client backend'
+                  AND state IS DISTINCT FROM 'idle'
+                  AND application_name NOT LIKE 'telemetrywatch-%'
+            )
+        "#,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(TenantStats {
+        counters,
+        connections,
+        size_bytes,
+        longest_transaction_seconds,
+        table_activity,
+        active_connections,
+    })
+}
+
+impl Database {
+    /// Active projects as (id, slug, decrypted db_url). The URL is `None` if it
+    /// could not be decrypted.
+    async fn list_tenant_databases(&self) -> anyhow::Result<Vec<(i64, String, Option<String>)>> {
+        let rows: Vec<(i64, String, String)> = sqlx::query_as(
+            "SELECT id, slug, db_url FROM platform_projects WHERE status = 'active' ORDER BY id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let tenants = rows
+            .into_iter()
+            .map(|(id, slug, db_url)| {
+                let db_url = self
+                    .open_project_db_url(id, &db_url)
+                    .map_err(|e| tracing::warn!("Cannot decrypt db_url of project {}: {}", slug, e))
+                    .ok();
+                (id, slug, db_url)
+            })
+            .collect();
+
+        Ok(tenants)
+    }
+    /// Keeps the latest size and connection count of each project that was
+    /// collected successfully, for the quota evaluator, and notes when it was
+    /// last seen active, for the idle detector: a new project, an active
+    /// connection or a change in table activity counts.
+    async fn record_tenant_usage(&self, results: &[CollectResult]) -> anyhow::Result<()> {
+        let collected: Vec<(i64, &TenantStats)> = results
+            .iter()
+            .filter_map(|r| r.stats.as_ref().map(|stats| (r.project_id, stats)))
+            .collect();
+        if collected.is_empty() {
+            return Ok(());
+        }
+
+        let mut upsert = sqlx::QueryBuilder::new(
+            "INSERT INTO platform_project_usage (project_id, db_size_bytes, connections, \
+             collected_at, table_activity, active_connections, last_active_at) ",
+        );
+        upsert.push_values(collected, |mut row, (project_id, stats)| {
+            row.push_bind(project_id)
+                .push_bind(stats.size_bytes)
+                .push_bind(stats.total_connections() as i32)
+                .push("NOW()")
+                .push_bind(stats.table_activity)
+                .push_bind(stats.active_connections as i32)
+                .push("NOW()");
+        });
+        upsert.push(
+            " ON CONFLICT (project_id) DO UPDATE SET db_size_bytes = EXCLUDED.db_size_bytes, \
+             connections = EXCLUDED.connections, collected_at = EXCLUDED.collected_at, \
+             table_activity = EXCLUDED.table_activity, \
+             active_connections = EXCLUDED.active_connections, \
+             last_active_at = CASE \
+                 WHEN EXCLUDED.active_connections > 0 \
+                   OR platform_project_usage.table_activity IS DISTINCT FROM EXCLUDED.table_activity \
+                   OR platform_project_usage.last_active_at IS NULL \
+                 THEN EXCLUDED.collected_at \
+                 ELSE platform_project_usage.last_active_at END",
+        );
+        upsert.build().execute(&self.pool).await?;
+
+        Ok(())
+    }
+}
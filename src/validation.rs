@@ -0,0 +1,85 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A single rejected request field.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[schema(as = FieldError)]
+pub struct FieldError {
+    /// Name of the offending field
+    #[schema(example = "region")]
+    pub field: String,
+    /// Why the value was rejected
+    #[schema(example = "must be one of: us-east-1, us-east-2, us-west-1")]
+    pub message: String,
+}
+
+/// Collects every invalid field of a request so clients can fix them in one go.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+#[schema(as = ValidationErrors)]
+pub struct ValidationErrors {
+    /// Summary of the failure
+    #[schema(example = "Validation failed")]
+    pub message: String,
+    /// One entry per invalid field
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self {
+            message: "Validation failed".to_string(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, field: &str, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Returns `value` if no errors were recorded, otherwise the collected errors.
+    pub fn into_result<T>(self, value: T) -> Result<T, Self> {
+        if self.is_empty() {
+            Ok(value)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<String> = self
+            .errors
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.message))
+            .collect();
+        write!(f, "{} ({})", self.message, fields.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Checks that `slug` is 3-63 lowercase letters, digits or hyphens, not starting or ending with a hyphen.
+pub fn is_valid_slug(slug: &str) -> bool {
+    (3..=63).contains(&slug.len())
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+}
+
+pub fn is_postgres_url(url: &str) -> bool {
+    url.starts_with("postgresql://") || url.starts_with("postgres://")
+}
+
+pub fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
@@ -0,0 +1,880 @@
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use sqlx::types::Json;
+use sqlx::{FromRow, Postgres, QueryBuilder};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::WebhookConfig;
+use crate::db::Database;
+use crate::metrics::Metrics;
+use crate::net::{is_public, IpNetworks};
+use crate::platform::{string_enum, PlatformProject, ProjectStatus};
+use crate::validation::{is_http_url, ValidationErrors};
+
+pub const SIGNATURE_HEADER: &str = "x-telemetrywatch-signature";
+pub const EVENT_HEADER: &str = "x-telemetrywatch-event";
+pub const DELIVERY_HEADER: &str = "x-telemetrywatch-delivery";
+
+/// Associated data binding encrypted signing secrets to their column.
+const SECRET_CONTEXT: &str = "webhook_subscriptions.secret";
+
+/// Prefix of generated signing secrets.
+const SECRET_PREFIX: &str = "whsec_";
+
+/// Longest wait between two attempts of a delivery.
+const MAX_BACKOFF_SECS: u64 = 6 * 3600;
+
+/// Deliveries attempted per round.
+const BATCH_SIZE: i64 = 50;
+
+/// Response bodies are kept in the delivery log up to this many bytes.
+const MAX_LOGGED_BODY: usize = 1024;
+
+const SUBSCRIPTION_COLUMNS: &str =
+    "id, url, description, event_types, active, created_by, created_at, updated_at";
+
+const DELIVERY_COLUMNS: &str = "id, subscription_id, event_id, event_type, payload, status, \
+     attempts, next_attempt_at, last_attempt_at, response_status, response_body, last_error, \
+     created_at, delivered_at";
+
+string_enum! {
+    /// Project lifecycle event a webhook can subscribe to
+    pub enum WebhookEventType {
+        Created => "project.created",
+        Suspended => "project.suspended",
+        Resumed => "project.resumed",
+        Deleted => "project.deleted",
+    }
+}
+
+string_enum! {
+    /// State of a webhook delivery
+    pub enum DeliveryStatus {
+        Pending => "pending",
+        Succeeded => "succeeded",
+        Failed => "failed",
+    }
+}
+
+impl WebhookEventType {
+    /// The event announced by a project moving from `from` to `to`, if any.
+    pub fn for_transition(from: ProjectStatus, to: ProjectStatus) -> Option<Self> {
+        match (from, to) {
+            (ProjectStatus::Provisioning, ProjectStatus::Active) => Some(WebhookEventType::Created),
+            (_, ProjectStatus::Suspended) => Some(WebhookEventType::Suspended),
+            (ProjectStatus::Suspended, ProjectStatus::Active) => Some(WebhookEventType::Resumed),
+            (_, ProjectStatus::Deleted) => Some(WebhookEventType::Deleted),
+            _ => None,
+        }
+    }
+}
+
+/// An endpoint that receives project lifecycle events.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct WebhookSubscription {
+    #[schema(example = 1)]
+    pub id: i64,
+    /// URL the events are POSTed to
+    #[schema(example = "https://hooks.example.com/telemetrywatch")]
+    pub url: String,
+    #[schema(example = "Billing sync")]
+    pub description: Option<String>,
+    /// Events delivered to the endpoint; empty means every event
+    pub event_types: Vec<WebhookEventType>,
+    /// Inactive subscriptions receive no new events and their pending deliveries wait
+    pub active: bool,
+    /// Actor that created the subscription
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request body for creating a webhook subscription
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateWebhookSubscription {
+    #[schema(example = "https://hooks.example.com/telemetrywatch")]
+    pub url: String,
+    #[schema(example = "Billing sync")]
+    pub description: Option<String>,
+    /// Events to deliver; omit or leave empty for every event
+    #[serde(default)]
+    #[schema(example = json!(["project.suspended", "project.resumed"]))]
+    pub event_types: Vec<String>,
+    /// Signing secret (at least 16 characters); generated if omitted
+    pub secret: Option<String>,
+}
+
+/// A validated [`CreateWebhookSubscription`].
+#[derive(Debug, Clone)]
+pub struct NewWebhookSubscription {
+    pub url: String,
+    pub description: Option<String>,
+    pub event_types: Vec<WebhookEventType>,
+    pub secret: String,
+}
+
+impl CreateWebhookSubscription {
+    pub fn validate(self) -> Result<NewWebhookSubscription, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        validate_url(&self.url, &mut errors);
+        let description = self.description.map(|d| d.trim().to_string());
+        validate_description(description.as_deref(), &mut errors);
+        let event_types = parse_event_types(&self.event_types, &mut errors);
+        let secret = match self.secret {
+            Some(secret) if secret.len() < 16 || secret.len() > 255 => {
+                errors.add("secret", "must be between 16 and 255 characters");
+                secret
+            }
+            Some(secret) => secret,
+            None => generate_secret(),
+        };
+
+        errors.into_result(NewWebhookSubscription {
+            url: self.url,
+            description,
+            event_types,
+            secret,
+        })
+    }
+}
+
+/// Request body for changing a webhook subscription; omitted fields are kept
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct UpdateWebhookSubscription {
+    #[schema(example = "https://hooks.example.com/telemetrywatch")]
+    pub url: Option<String>,
+    pub description: Option<String>,
+    /// Events to deliver; empty for every event
+    #[schema(example = json!(["project.deleted"]))]
+    pub event_types: Option<Vec<String>>,
+    pub active: Option<bool>,
+}
+
+/// An [`UpdateWebhookSubscription`] that has passed validation.
+#[derive(Debug, Clone, Default)]
+pub struct WebhookSubscriptionChanges {
+    pub url: Option<String>,
+    pub description: Option<String>,
+    pub event_types: Option<Vec<WebhookEventType>>,
+    pub active: Option<bool>,
+}
+
+impl UpdateWebhookSubscription {
+    pub fn validate(self) -> Result<WebhookSubscriptionChanges, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        if let Some(url) = &self.url {
+            validate_url(url, &mut errors);
+        }
+        let description = self.description.map(|d| d.trim().to_string());
+        validate_description(description.as_deref(), &mut errors);
+        let event_types = self
+            .event_types
+            .map(|types| parse_event_types(&types, &mut errors));
+
+        errors.into_result(WebhookSubscriptionChanges {
+            url: self.url,
+            description,
+            event_types,
+            active: self.active,
+        })
+    }
+}
+
+fn validate_url(url: &str, errors: &mut ValidationErrors) {
+    if !is_http_url(url) || url.len() > 2048 {
+        errors.add(
+            "url",
+            "must be an http:// or https:// URL of at most 2048 characters",
+        );
+    }
+}
+
+fn validate_description(description: Option<&str>, errors: &mut ValidationErrors) {
+    if description.is_some_and(|d| d.len() > 255) {
+        errors.add("description", "must be at most 255 characters");
+    }
+}
+
+fn parse_event_types(types: &[String], errors: &mut ValidationErrors) -> Vec<WebhookEventType> {
+    let mut event_types = Vec::new();
+    for event_type in types {
+        match event_type.parse::<WebhookEventType>() {
+            Ok(event_type) if !event_types.contains(&event_type) => event_types.push(event_type),
+            Ok(_) => {}
+            Err(e) => errors.add("event_types", format!("'{}' {}", event_type, e)),
+        }
+    }
+    event_types
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 24];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", SECRET_PREFIX, hex::encode(bytes))
+}
+
+/// A freshly created subscription. `secret` is returned once; it is stored encrypted.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CreatedWebhookSubscription {
+    #[serde(flatten)]
+    pub subscription: WebhookSubscription,
+    /// Key of the HMAC-SHA256 signature on every delivery
+    #[schema(example = "whsec_3f9a1c2e5b7d...")]
+    pub secret: String,
+}
+
+/// One event queued for, or delivered to, a subscription.
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct WebhookDelivery {
+    #[schema(example = 1)]
+    pub id: i64,
+    pub subscription_id: i64,
+    /// ID of the event, the same for every subscription it is delivered to
+    #[schema(example = "2d0f7c9e-5f7b-4a8e-9d55-3c2c7f1f0b1a")]
+    pub event_id: String,
+    pub event_type: WebhookEventType,
+    /// The body that is POSTed
+    #[schema(value_type = Object)]
+    pub payload: Json<Value>,
+    pub status: DeliveryStatus,
+    /// Attempts made so far
+    pub attempts: i32,
+    /// When the next attempt is due, while pending
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    /// HTTP status of the last attempt, if the endpoint answered
+    pub response_status: Option<i32>,
+    /// Start of the response body of the last attempt
+    pub response_body: Option<String>,
+    /// Why the last attempt failed
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// Query parameters for listing deliveries.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveryQuery {
+    /// Only deliveries in this state
+    pub status: Option<DeliveryStatus>,
+    /// Maximum number of deliveries to return, newest first (default 50, max 1000)
+    pub limit: Option<i64>,
+}
+
+impl DeliveryQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(50).clamp(1, 1000)
+    }
+}
+
+/// Value of the signature header: `t=<unix time>,v1=<hex HMAC-SHA256 of "<t>.<body>">`.
+/// Receivers recompute the HMAC with the subscription's secret and reject old timestamps.
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+/// Wait before the attempt following attempt number `attempts`: `base_secs`
+/// doubled for every earlier attempt, up to six hours.
+fn backoff(base_secs: u64, attempts: i32) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+    Duration::from_secs(
+        base_secs
+            .saturating_mul(1 << exponent)
+            .min(MAX_BACKOFF_SECS),
+    )
+}
+
+/// A delivery claimed for an attempt, with its subscription's endpoint.
+#[derive(FromRow)]
+struct DueDelivery {
+    id: i64,
+    event_type: WebhookEventType,
+    payload: Json<Value>,
+    attempts: i32,
+    url: String,
+    secret: String,
+    /// When this attempt was claimed; with `attempts`, fences its result
+    claimed_at: DateTime<Utc>,
+}
+
+/// Result of one delivery attempt.
+struct Attempt {
+    response_status: Option<i32>,
+    response_body: Option<String>,
+    error: Option<String>,
+}
+
+impl Attempt {
+    fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Resolves webhook hosts to the addresses deliveries may reach: public ones,
+/// and those in `WEBHOOK_ALLOWED_NETWORKS`. Checking the resolved addresses,
+/// rather than the URL when it is saved, keeps subscriptions from reaching
+/// internal services through DNS names that point (or later change) to them.
+struct PermittedResolver {
+    allowed: Arc<IpNetworks>,
+}
+
+impl Resolve for PermittedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allowed = self.allowed.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| is_permitted(&allowed, addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} does not resolve to a permitted address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_permitted(allowed: &IpNetworks, ip: IpAddr) -> bool {
+    is_public(ip) || allowed.contains(ip)
+}
+
+/// POSTs queued webhook deliveries, retrying failures with exponential backoff
+/// until `max_attempts` is reached.
+pub struct WebhookDispatcher {
+    db: Arc<Database>,
+    metrics: Arc<Metrics>,
+    http: reqwest::Client,
+    allowed: Arc<IpNetworks>,
+    config: WebhookConfig,
+}
+
+impl WebhookDispatcher {
+    pub fn new(
+        db: Arc<Database>,
+        metrics: Arc<Metrics>,
+        config: WebhookConfig,
+    ) -> anyhow::Result<Arc<Self>> {
+        let allowed = Arc::new(
+            IpNetworks::parse(&config.allowed_networks)
+                .map_err(|entry| anyhow::anyhow!("invalid webhook allowed network '{}'", entry))?,
+        );
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .user_agent(concat!(
+                "TelemetryWatch-Webhooks/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .redirect(reqwest::redirect::Policy::none())
+            // A proxy would resolve the host itself, past the resolver's checks
+            .no_proxy()
+            .dns_resolver(Arc::new(PermittedResolver {
+                allowed: allowed.clone(),
+            }))
+            .build()?;
+
+        Ok(Arc::new(Self {
+            db,
+            metrics,
+            http,
+            allowed,
+            config,
+        }))
+    }
+
+    /// Delivers due events every `poll_interval_secs`. Meant to run under
+    /// [`crate::tasks::spawn_supervised`].
+    pub async fn run(self: Arc<Self>) -> anyhow::Result<()> {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(self.config.poll_interval_secs));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            // Keep going while full batches come back, so a backlog drains quickly
+            while self.deliver_due().await? == BATCH_SIZE as usize {}
+
+            let pending = self.db.count_pending_webhook_deliveries().await?;
+            self.metrics.webhook_deliveries_pending.set(pending as f64);
+            self.db
+                .prune_webhook_deliveries(self.config.retention_days)
+                .await?;
+        }
+    }
+
+    /// Attempts one batch of due deliveries concurrently. Returns the batch size.
+    async fn deliver_due(self: &Arc<Self>) -> anyhow::Result<usize> {
+        // Claimed deliveries are not due again until every attempt has timed out
+        let lease = Duration::from_secs(self.config.timeout_secs + 30);
+        let due = self.db.claim_webhook_deliveries(BATCH_SIZE, lease).await?;
+        let count = due.len();
+
+        let mut deliveries = JoinSet::new();
+        for delivery in due {
+            deliveries.spawn(self.clone().deliver(delivery));
+        }
+        while let Some(result) = deliveries.join_next().await {
+            result?;
+        }
+
+        Ok(count)
+    }
+
+    async fn deliver(self: Arc<Self>, delivery: DueDelivery) {
+        let attempt = self.attempt(&delivery).await;
+        let result = if attempt.succeeded() {
+            "success"
+        } else {
+            "failure"
+        };
+        self.metrics
+            .webhook_delivery_attempts_total
+            .with_label_values(&[delivery.event_type.as_str(), result])
+            .inc();
+
+        let exhausted = !attempt.succeeded() && delivery.attempts >= self.config.max_attempts;
+        if exhausted {
+            self.metrics
+                .webhook_deliveries_failed_total
+                .with_label_values(&[delivery.event_type.as_str()])
+                .inc();
+            tracing::warn!(
+                "Giving up on webhook delivery {} after {} attempts: {}",
+                delivery.id,
+                delivery.attempts,
+                attempt.error.as_deref().unwrap_or_default()
+            );
+        }
+        let retry_in = backoff(self.config.retry_base_secs, delivery.attempts);
+        match self
+            .db
+            .record_webhook_attempt(&delivery, &attempt, exhausted, retry_in)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => tracing::warn!(
+                "Discarding result of webhook delivery {} attempt {}: it was claimed again meanwhile",
+                delivery.id,
+                delivery.attempts
+            ),
+            Err(e) => tracing::error!("Failed to record webhook delivery {}: {}", delivery.id, e),
+        }
+    }
+
+    async fn attempt(&self, delivery: &DueDelivery) -> Attempt {
+        // Hosts given as addresses are not resolved, so they are checked here
+        let literal = reqwest::Url::parse(&delivery.url).ok().and_then(|url| {
+            let host = url.host_str()?;
+            host.trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse::<IpAddr>()
+                .ok()
+        });
+        if let Some(ip) = literal.filter(|ip| !is_permitted(&self.allowed, *ip)) {
+            return Attempt {
+                response_status: None,
+                response_body: None,
+                error: Some(format!("{} is not a permitted address", ip)),
+            };
+        }
+        let secret = match self.db.open_secret(&delivery.secret, SECRET_CONTEXT) {
+            Ok(secret) => secret,
+            Err(e) => {
+                return Attempt {
+                    response_status: None,
+                    response_body: None,
+                    error: Some(format!("cannot decrypt signing secret: {}", e)),
+                }
+            }
+        };
+        let body = match serde_json::to_vec(&delivery.payload.0) {
+            Ok(body) => body,
+            Err(e) => {
+                return Attempt {
+                    response_status: None,
+                    response_body: None,
+                    error: Some(format!("cannot serialize payload: {}", e)),
+                }
+            }
+        };
+
+        let started = Instant::now();
+        let result = self
+            .http
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, delivery.event_type.as_str())
+            .header(DELIVERY_HEADER, delivery.id.to_string())
+            .header(
+                SIGNATURE_HEADER,
+                sign(&secret, Utc::now().timestamp(), &body),
+            )
+            .body(body)
+            .send()
+            .await;
+        self.metrics
+            .webhook_delivery_duration_seconds
+            .observe(started.elapsed().as_secs_f64());
+
+        match result {
+            Ok(response) => {
+                let status = response.status();
+                let text = response.text().await.unwrap_or_default();
+                let mut end = text.len().min(MAX_LOGGED_BODY);
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                Attempt {
+                    response_status: Some(status.as_u16() as i32),
+                    response_body: Some(text[..end].to_string()),
+                    error: (!status.is_success()).then(|| format!("endpoint answered {}", status)),
+                }
+            }
+            Err(e) => Attempt {
+                response_status: None,
+                response_body: None,
+                // With its causes, such as the resolver refusing the host
+                error: Some(format!("{:#}", anyhow::Error::from(e))),
+            },
+        }
+    }
+}
+
+/// Queues `event_type` about `project` for every active subscription to it, as
+/// part of the transaction that made the change.
+pub(crate) async fn enqueue_webhook_event(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    event_type: WebhookEventType,
+    project: &PlatformProject,
+    previous_status: Option<ProjectStatus>,
+    actor: &str,
+    reason: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let event_id = uuid::Uuid::new_v4().to_string();
+    let payload = serde_json::json!({
+        "id": event_id,
+        "type": event_type,
+        "created_at": Utc::now(),
+        "data": {
+            "project": project,
+            "previous_status": previous_status,
+            "actor": actor,
+            "reason": reason,
+        },
+    });
+
+    sqlx::query(
+        r#"
+        INSERT INTO webhook_deliveries (subscription_id, event_id, event_type, payload)
+        SELECT id, $1, $2, $3
+        FROM webhook_subscriptions
+        WHERE active AND (cardinality(event_types) = 0 OR $2 = ANY(event_types))
+        "#,
+    )
+    .bind(&event_id)
+    .bind(event_type)
+    .bind(Json(payload))
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+impl Database {
+    /// Creates a subscription, storing its secret encrypted.
+    pub async fn create_webhook_subscription(
+        &self,
+        input: NewWebhookSubscription,
+        created_by: &str,
+    ) -> anyhow::Result<CreatedWebhookSubscription> {
+        let sealed = self.seal_secret(&input.secret, SECRET_CONTEXT)?;
+        let subscription = sqlx::query_as::<_, WebhookSubscription>(&format!(
+            r#"
+            INSERT INTO webhook_subscriptions (url, description, event_types, secret, created_by)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {SUBSCRIPTION_COLUMNS}
+            "#,
+        ))
+        .bind(&input.url)
+        .bind(&input.description)
+        .bind(&input.event_types)
+        .bind(&sealed)
+        .bind(created_by)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(CreatedWebhookSubscription {
+            subscription,
+            secret: input.secret,
+        })
+    }
+
+    pub async fn list_webhook_subscriptions(&self) -> anyhow::Result<Vec<WebhookSubscription>> {
+        let subscriptions = sqlx::query_as::<_, WebhookSubscription>(&format!(
+            "SELECT {SUBSCRIPTION_COLUMNS} FROM webhook_subscriptions ORDER BY id"
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(subscriptions)
+    }
+
+    pub async fn get_webhook_subscription(
+        &self,
+        id: i64,
+    ) -> anyhow::Result<Option<WebhookSubscription>> {
+        let subscription = sqlx::query_as::<_, WebhookSubscription>(&format!(
+            "SELECT {SUBSCRIPTION_COLUMNS} FROM webhook_subscriptions WHERE id = $1"
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(subscription)
+    }
+
+    /// Applies a partial update. Returns `None` if the subscription does not exist.
+    pub async fn update_webhook_subscription(
+        &self,
+        id: i64,
+        changes: WebhookSubscriptionChanges,
+    ) -> anyhow::Result<Option<WebhookSubscription>> {
+        let subscription = sqlx::query_as::<_, WebhookSubscription>(&format!(
+            r#"
+            UPDATE webhook_subscriptions
+            SET url = COALESCE($2, url),
+                description = COALESCE($3, description),
+                event_types = COALESCE($4, event_types),
+                active = COALESCE($5, active),
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING {SUBSCRIPTION_COLUMNS}
+            "#,
+        ))
+        .bind(id)
+        .bind(&changes.url)
+        .bind(&changes.description)
+        .bind(&changes.event_types)
+        .bind(changes.active)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(subscription)
+    }
+
+    /// Deletes a subscription along with its deliveries. Returns `false` if it does not exist.
+    pub async fn delete_webhook_subscription(&self, id: i64) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM webhook_subscriptions WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Deliveries of a subscription, newest first.
+    pub async fn list_webhook_deliveries(
+        &self,
+        subscription_id: i64,
+        query: &DeliveryQuery,
+    ) -> anyhow::Result<Vec<WebhookDelivery>> {
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+            "SELECT {DELIVERY_COLUMNS} FROM webhook_deliveries WHERE subscription_id = "
+        ));
+        builder.push_bind(subscription_id);
+        if let Some(status) = query.status {
+            builder.push(" AND status = ").push_bind(status);
+        }
+        builder
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(query.limit());
+
+        let deliveries = builder
+            .build_query_as::<WebhookDelivery>()
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(deliveries)
+    }
+
+    /// Queues a delivery to be sent again right away with a fresh set of attempts.
+    /// Returns `None` if it does not belong to the subscription.
+    pub async fn redeliver_webhook(
+        &self,
+        subscription_id: i64,
+        delivery_id: i64,
+    ) -> anyhow::Result<Option<WebhookDelivery>> {
+        let delivery = sqlx::query_as::<_, WebhookDelivery>(&format!(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = 'pending', attempts = 0, next_attempt_at = NOW(), delivered_at = NULL
+            WHERE id = $1 AND subscription_id = $2
+            RETURNING {DELIVERY_COLUMNS}
+            "#,
+        ))
+        .bind(delivery_id)
+        .bind(subscription_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(delivery)
+    }
+
+    /// Takes up to `limit` due deliveries of active subscriptions, counting an
+    /// attempt and pushing their next attempt back by `lease` so that no other
+    /// instance picks them up meanwhile.
+    async fn claim_webhook_deliveries(
+        &self,
+        limit: i64,
+        lease: Duration,
+    ) -> anyhow::Result<Vec<DueDelivery>> {
+        let due = sqlx::query_as::<_, DueDelivery>(
+            r#"
+            UPDATE webhook_deliveries d
+            SET attempts = d.attempts + 1,
+                last_attempt_at = NOW(),
+                next_attempt_at = NOW() + make_interval(secs => $2)
+            FROM webhook_subscriptions s
+            WHERE s.id = d.subscription_id
+              AND d.id IN (
+                  SELECT dd.id
+                  FROM webhook_deliveries dd
+                  JOIN webhook_subscriptions ss ON ss.id = dd.subscription_id
+                  WHERE dd.status = 'pending' AND dd.next_attempt_at <= NOW() AND ss.active
+                  ORDER BY dd.next_attempt_at
+                  LIMIT $1
+                  FOR UPDATE OF dd SKIP LOCKED
+              )
+            RETURNING d.id, d.event_type, d.payload, d.attempts, s.url, s.secret,
+                      d.last_attempt_at AS claimed_at
+            "#,
+        )
+        .bind(limit)
+        .bind(lease.as_secs_f64())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(due)
+    }
+
+    /// Records the outcome of `delivery`'s attempt. Returns `false`, recording
+    /// nothing, if the delivery was claimed again (its lease ran out) or
+    /// redelivered since, so a late result cannot overwrite a newer one.
+    async fn record_webhook_attempt(
+        &self,
+        delivery: &DueDelivery,
+        attempt: &Attempt,
+        exhausted: bool,
+        retry_in: Duration,
+    ) -> anyhow::Result<bool> {
+        let status = if attempt.succeeded() {
+            DeliveryStatus::Succeeded
+        } else if exhausted {
+            DeliveryStatus::Failed
+        } else {
+            DeliveryStatus::Pending
+        };
+        let result = sqlx::query(
+            r#"
+            UPDATE webhook_deliveries
+            SET status = $2,
+                response_status = $3,
+                response_body = $4,
+                last_error = $5,
+                next_attempt_at = CASE WHEN $2 = 'pending'
+                                       THEN NOW() + make_interval(secs => $6)
+                                       ELSE next_attempt_at END,
+                delivered_at = CASE WHEN $2 = 'succeeded' THEN NOW() END
+            WHERE id = $1 AND status = 'pending' AND attempts = $7 AND last_attempt_at = $8
+            "#,
+        )
+        .bind(delivery.id)
+        .bind(status)
+        .bind(attempt.response_status)
+        .bind(&attempt.response_body)
+        .bind(&attempt.error)
+        .bind(retry_in.as_secs_f64())
+        .bind(delivery.attempts)
+        .bind(delivery.claimed_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn count_pending_webhook_deliveries(&self) -> anyhow::Result<i64> {
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM webhook_deliveries WHERE status = 'pending'")
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(count)
+    }
+
+    /// Deletes finished deliveries older than `retention_days`.
+    async fn prune_webhook_deliveries(&self, retention_days: i64) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+            DELETE FROM webhook_deliveries
+            WHERE status <> 'pending' AND created_at < NOW() - make_interval(days => $1)
+            "#,
+        )
+        .bind(retention_days as i32)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_timestamp_and_body() {
+        let body = br#"{"event":"project.created"}"#;
+
+        assert_eq!(
+            sign("whsec_test", 1_700_000_000, body),
+            "t=1700000000,v1=afcf42735b8a21c6cc8546b9a80c849afc5d72fa1780256f7a45b439a6e326a3"
+        );
+        assert_ne!(
+            sign("whsec_test", 1_700_000_001, body),
+            sign("whsec_test", 1_700_000_000, body)
+        );
+        assert_ne!(
+            sign("whsec_other", 1_700_000_000, body),
+            sign("whsec_test", 1_700_000_000, body)
+        );
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_six_hours() {
+        assert_eq!(backoff(30, 1), Duration::from_secs(30));
+        assert_eq!(backoff(30, 2), Duration::from_secs(60));
+        assert_eq!(backoff(30, 4), Duration::from_secs(240));
+        assert_eq!(backoff(30, 0), Duration::from_secs(30));
+        assert_eq!(backoff(30, 100), Duration::from_secs(MAX_BACKOFF_SECS));
+    }
+}